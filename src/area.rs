@@ -0,0 +1,151 @@
+use crate::cli::{AreaMode, Coords, PruneArgs};
+use crate::rect_intersects_bounds;
+
+use anyhow::{bail, Context, Result};
+use std::fs::read_to_string;
+use std::path::Path;
+
+#[derive(Debug)]
+pub(crate) enum Shape {
+    Box {
+        from: Option<Coords>,
+        to: Option<Coords>,
+    },
+    Circle {
+        center: (i32, i32),
+        radius: f64,
+    },
+    Polygon(Vec<(f64, f64)>),
+}
+
+impl Shape {
+    /// Whether the chunk at chunk coordinates `x`, `z` is inside the shape
+    pub(crate) fn contains_chunk(&self, x: i32, z: i32) -> bool {
+        let cf = (x * 16, z * 16);
+        let ct = (x * 16 + 15, z * 16 + 15);
+        match self {
+            Shape::Box { from, to } => rect_intersects_bounds(cf, ct, from.as_ref(), to.as_ref()),
+            Shape::Circle { center, radius } => {
+                // distance from the center to the closest block of the chunk
+                let dx = (center.0.clamp(cf.0, ct.0) - center.0) as f64;
+                let dz = (center.1.clamp(cf.1, ct.1) - center.1) as f64;
+                dx * dx + dz * dz <= radius * radius
+            }
+            Shape::Polygon(points) => {
+                let center = (cf.0 as f64 + 8.0, cf.1 as f64 + 8.0);
+                polygon_contains(points, center)
+            }
+        }
+    }
+}
+
+/// Even-odd rule point in polygon test
+fn polygon_contains(points: &[(f64, f64)], p: (f64, f64)) -> bool {
+    let mut inside = false;
+    let mut j = points.len() - 1;
+    for i in 0..points.len() {
+        let (xi, zi) = points[i];
+        let (xj, zj) = points[j];
+        if (zi > p.1) != (zj > p.1) && p.0 < (xj - xi) * (p.1 - zi) / (zj - zi) + xi {
+            inside = !inside;
+        }
+        j = i;
+    }
+    inside
+}
+
+/// Reads a polygon from a file with one `x,z` block coordinate per line.
+/// Empty lines and lines starting with `#` are ignored.
+pub(crate) fn load_polygon(path: &Path) -> Result<Vec<(f64, f64)>> {
+    let text =
+        read_to_string(path).with_context(|| format!("Failed to read `{}`", path.display()))?;
+    let mut points = vec![];
+    for (n, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let (x, z) = line
+            .split_once(',')
+            .or_else(|| line.split_once(char::is_whitespace))
+            .with_context(|| format!("{}:{}: expected x,z", path.display(), n + 1))?;
+        let x: f64 = x.trim().parse()?;
+        let z: f64 = z.trim().parse()?;
+        points.push((x, z));
+    }
+    if points.len() < 3 {
+        bail!("{}: a polygon needs at least 3 points", path.display());
+    }
+    Ok(points)
+}
+
+/// The part of the world selected with `--from/--to`, `--center/--radius`
+/// and `--polygon`. When several shapes are given, the area is their union.
+#[derive(Debug)]
+pub(crate) struct Area {
+    shapes: Vec<Shape>,
+    mode: AreaMode,
+}
+
+impl Area {
+    pub(crate) fn from_args(args: &PruneArgs) -> Result<Option<Area>> {
+        let mut shapes = vec![];
+        if args.from.is_some() || args.to.is_some() {
+            shapes.push(Shape::Box {
+                from: args.from,
+                to: args.to,
+            });
+        }
+        if let (Some(center), Some(radius)) = (args.center, args.radius) {
+            shapes.push(Shape::Circle { center, radius });
+        }
+        if let Some(ref polygon) = args.polygon {
+            shapes.push(Shape::Polygon(load_polygon(polygon)?));
+        }
+        if shapes.is_empty() {
+            return Ok(None);
+        }
+        Ok(Some(Area {
+            shapes,
+            mode: args.keep.clone(),
+        }))
+    }
+
+    /// Whether the chunk may be kept. Chunks on the wrong side of the area
+    /// are pruned regardless of their InhabitedTime.
+    pub(crate) fn keeps(&self, x: i32, z: i32) -> bool {
+        let inside = self.shapes.iter().any(|s| s.contains_chunk(x, z));
+        match self.mode {
+            AreaMode::Inside => inside,
+            AreaMode::Outside => !inside,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn polygon() {
+        let square = vec![(0.0, 0.0), (32.0, 0.0), (32.0, 32.0), (0.0, 32.0)];
+        let shape = Shape::Polygon(square);
+        assert!(shape.contains_chunk(0, 0));
+        assert!(shape.contains_chunk(1, 1));
+        assert!(!shape.contains_chunk(2, 0));
+        assert!(!shape.contains_chunk(-1, 0));
+    }
+
+    #[test]
+    fn circle() {
+        let shape = Shape::Circle {
+            center: (0, 0),
+            radius: 20.0,
+        };
+        assert!(shape.contains_chunk(0, 0));
+        assert!(shape.contains_chunk(-1, -1));
+        assert!(shape.contains_chunk(1, 0));
+        assert!(!shape.contains_chunk(1, 1));
+        assert!(!shape.contains_chunk(2, 0));
+    }
+}
@@ -0,0 +1,157 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File};
+use std::path::{absolute, Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const JOURNAL_FILE: &str = "journal.json";
+
+#[derive(Serialize, Deserialize, Debug)]
+pub(crate) struct JournalEntry {
+    pub(crate) original: PathBuf,
+    /// `None` if the file didn't exist before the command ran
+    pub(crate) backup: Option<PathBuf>,
+    pub(crate) changes: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub(crate) struct Journal {
+    pub(crate) command: String,
+    /// seconds since the unix epoch
+    pub(crate) created: u64,
+    pub(crate) entries: Vec<JournalEntry>,
+}
+
+/// Keeps a copy of every file a command modifies, along with a journal
+/// describing what was done, so that `mcl restore` can undo it.
+///
+/// The backup directory is only created once the first file is saved.
+pub(crate) struct Backup {
+    dir: PathBuf,
+    journal: Mutex<Journal>,
+}
+
+impl Backup {
+    pub(crate) fn new(root: &Path, command: &str) -> Result<Backup> {
+        let created = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+        let mut dir = root.join(format!("{created}-{command}"));
+        let mut n = 1;
+        while dir.exists() {
+            dir = root.join(format!("{created}-{command}-{n}"));
+            n += 1;
+        }
+        Ok(Backup {
+            dir: absolute(dir)?,
+            journal: Mutex::new(Journal {
+                command: command.to_string(),
+                created,
+                entries: vec![],
+            }),
+        })
+    }
+
+    /// Copies `path` into the backup directory. Must be called before the
+    /// file is modified. Saving the same file twice keeps the first copy.
+    pub(crate) fn save(&self, path: &Path) -> Result<()> {
+        let original = absolute(path)?;
+        let mut journal = self.journal.lock().unwrap();
+        if journal.entries.iter().any(|e| e.original == original) {
+            return Ok(());
+        }
+
+        fs::create_dir_all(&self.dir)
+            .with_context(|| format!("Failed to create `{}`", self.dir.display()))?;
+        let backup = if original.exists() {
+            let name = original.file_name().context("reading file name")?;
+            let backup = self.dir.join(format!(
+                "{}-{}",
+                journal.entries.len(),
+                name.to_string_lossy()
+            ));
+            fs::copy(&original, &backup)
+                .with_context(|| format!("Failed to back up `{}`", original.display()))?;
+            log::debug!("saved {} to {}", original.display(), backup.display());
+            Some(backup)
+        } else {
+            None
+        };
+
+        journal.entries.push(JournalEntry {
+            original,
+            backup,
+            changes: vec![],
+        });
+        self.write_journal(&journal)
+    }
+
+    /// Records a change made to a file previously passed to [`Backup::save`].
+    pub(crate) fn record(&self, path: &Path, change: String) -> Result<()> {
+        let original = absolute(path)?;
+        let mut journal = self.journal.lock().unwrap();
+        let entry = journal
+            .entries
+            .iter_mut()
+            .find(|e| e.original == original)
+            .with_context(|| format!("`{}` was not backed up", original.display()))?;
+        entry.changes.push(change);
+        self.write_journal(&journal)
+    }
+
+    /// Writes the journal next to its final place and renames it over the
+    /// old one, so an interrupted write never leaves it truncated.
+    fn write_journal(&self, journal: &Journal) -> Result<()> {
+        let path = self.dir.join(JOURNAL_FILE);
+        let tmp = path.with_extension("json.mcl-tmp");
+        {
+            let file = File::create(&tmp)?;
+            serde_json::to_writer_pretty(&file, journal)?;
+            file.sync_all()?;
+        }
+        fs::rename(&tmp, &path).with_context(|| format!("Failed to write `{}`", path.display()))?;
+        Ok(())
+    }
+
+    /// Logs where the journal was written, if anything was backed up.
+    pub(crate) fn finish(&self) {
+        let journal = self.journal.lock().unwrap();
+        if journal.entries.is_empty() {
+            return;
+        }
+        log::info!(
+            "{} files backed up, undo with: mcl restore {}",
+            journal.entries.len(),
+            self.dir.join(JOURNAL_FILE).display()
+        );
+    }
+}
+
+/// Puts back the original files recorded in a journal, removing the ones the
+/// command created.
+pub(crate) fn restore(journal_path: &Path) -> Result<()> {
+    let journal_path = if journal_path.is_dir() {
+        journal_path.join(JOURNAL_FILE)
+    } else {
+        journal_path.to_path_buf()
+    };
+    let file = File::open(&journal_path)
+        .with_context(|| format!("Failed to open `{}`", journal_path.display()))?;
+    let journal: Journal = serde_json::from_reader(file)?;
+
+    for entry in journal.entries.iter().rev() {
+        match &entry.backup {
+            Some(backup) => {
+                fs::copy(backup, &entry.original)
+                    .with_context(|| format!("Failed to restore `{}`", entry.original.display()))?;
+                println!("restored {}", entry.original.display());
+            }
+            None => {
+                if entry.original.exists() {
+                    fs::remove_file(&entry.original)?;
+                    println!("removed {}", entry.original.display());
+                }
+            }
+        }
+    }
+    Ok(())
+}
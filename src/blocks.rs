@@ -0,0 +1,256 @@
+use crate::cli::{BlockArgs, Coords, Dimension, OutputFormat};
+use crate::nbt::{load_chunk, Section};
+use crate::prune::list_region_files;
+use crate::region::{dimension_dir, region_coords};
+use crate::selector::BlockSelector;
+use crate::{rect_intersects_bounds, within_bounds};
+
+use anyhow::Result;
+use fastanvil::{Block, BlockData, Region};
+use serde::Serialize;
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+use std::fs::File;
+use std::path::Path;
+
+/// Palette indices of the 4096 blocks of a section, in YZX order. Sections
+/// made of a single block have no data, but still have 4096 blocks.
+pub(crate) fn section_indices(
+    block_states: &BlockData<Block>,
+) -> Option<Box<dyn Iterator<Item = usize> + '_>> {
+    if block_states.palette().len() == 1 {
+        return Some(Box::new(std::iter::repeat_n(0, 4096)));
+    }
+    let indices = block_states.try_iter_indices()?;
+    Some(Box::new(indices))
+}
+
+/// Calls `f` with the position of every block within the bounds matching
+/// any of the selectors, along with the section it belongs to.
+pub(crate) fn for_each_block(
+    world: &Path,
+    dimension: &Dimension,
+    selectors: &[BlockSelector],
+    from: Option<&Coords>,
+    to: Option<&Coords>,
+    mut f: impl FnMut(Coords, &Block, &Section),
+) -> Result<()> {
+    let region_dir = dimension_dir(world, dimension).join("region");
+    let region_files = list_region_files(&region_dir)?;
+    for reg_file in region_files {
+        let (reg_x, reg_z) = region_coords(&reg_file)?;
+
+        let rf = (reg_x * 512, reg_z * 512);
+        let rt = (reg_x * 512 + 511, reg_z * 512 + 511);
+        if !rect_intersects_bounds(rf, rt, from, to) {
+            log::debug!("region {reg_x} {reg_z} doesn't intersect bounds, skipping");
+            continue;
+        }
+
+        log::debug!("reading region {}", reg_file.display());
+        let file = File::options().read(true).write(false).open(&reg_file)?;
+        let mut reg = match Region::from_stream(file) {
+            Ok(reg) => reg,
+            Err(e) => {
+                log::debug!("error reading region {}: {}", reg_file.display(), e);
+                continue;
+            }
+        };
+
+        for raw_chunk in reg.iter() {
+            let raw_chunk = raw_chunk?;
+            let x = reg_x * 32 + raw_chunk.x as i32;
+            let z = reg_z * 32 + raw_chunk.z as i32;
+
+            let cf = (x * 16, z * 16);
+            let ct = (x * 16 + 15, z * 16 + 15);
+            if !rect_intersects_bounds(cf, ct, from, to) {
+                log::debug!("chunk {x} {z} doesn't intersect bounds, skipping");
+                continue;
+            }
+
+            let chunk = match load_chunk(raw_chunk.data.as_slice()) {
+                Ok(c) => c,
+                Err(e) => {
+                    log::debug!("error reading chunk {x} {z}: {}", e);
+                    continue;
+                }
+            };
+            for section in chunk.sections() {
+                let mut ids: HashSet<usize> = HashSet::new();
+                let Some(ref block_states) = section.block_states else {
+                    continue;
+                };
+                for (id, block) in block_states.palette().iter().enumerate() {
+                    if block.name() == "minecraft:air" {
+                        continue;
+                    }
+                    if selectors.iter().any(|s| s.matches(block)) {
+                        ids.insert(id);
+                    }
+                }
+                if ids.is_empty() {
+                    continue;
+                }
+
+                let Some(indices) = section_indices(block_states) else {
+                    continue;
+                };
+
+                for (i, palette_index) in indices.enumerate() {
+                    if !ids.contains(&palette_index) {
+                        continue;
+                    }
+                    let bx = x * 16 + (i & 0x000F) as i32;
+                    let by = section.y as i32 * 16 + ((i & 0x0F00) >> 8) as i32;
+                    let bz = z * 16 + ((i & 0x00F0) >> 4) as i32;
+                    if !within_bounds(&(bx, by, bz), from, to) {
+                        continue;
+                    }
+                    f(
+                        (bx, by, bz),
+                        &block_states.palette()[palette_index],
+                        section,
+                    );
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+pub(crate) fn blocks(
+    world: &Path,
+    dimension: Dimension,
+    selectors: &[BlockSelector],
+    from: Option<Coords>,
+    to: Option<Coords>,
+) -> Result<()> {
+    for_each_block(
+        world,
+        &dimension,
+        selectors,
+        from.as_ref(),
+        to.as_ref(),
+        |(x, y, z), block, _| {
+            println!("{x} {y} {z} {:#?}", block.name());
+        },
+    )
+}
+
+#[derive(Serialize, Default, Debug)]
+struct Aggregate {
+    blocks: BTreeMap<String, u64>,
+    y: BTreeMap<String, BTreeMap<i32, u64>>,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    chunks: BTreeMap<String, BTreeMap<String, u64>>,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    biomes: BTreeMap<String, BTreeMap<String, u64>>,
+}
+
+/// Counts the matching blocks per id and per Y level, and optionally per
+/// chunk and per biome.
+pub(crate) fn aggregate(args: &BlockArgs, selectors: &[BlockSelector]) -> Result<()> {
+    let mut agg = Aggregate::default();
+    for_each_block(
+        &args.world,
+        &args.dimension,
+        selectors,
+        args.from.as_ref(),
+        args.to.as_ref(),
+        |(x, y, z), block, section| {
+            let name = block.name();
+            *agg.blocks.entry(name.to_string()).or_default() += 1;
+            *agg.y
+                .entry(name.to_string())
+                .or_default()
+                .entry(y)
+                .or_default() += 1;
+            if args.per_chunk {
+                let chunk = format!("{},{}", x >> 4, z >> 4);
+                *agg.chunks
+                    .entry(name.to_string())
+                    .or_default()
+                    .entry(chunk)
+                    .or_default() += 1;
+            }
+            if args.per_biome {
+                let biome = section
+                    .biome_at((x & 15) as usize, (y & 15) as usize, (z & 15) as usize)
+                    .unwrap_or("unknown");
+                *agg.biomes
+                    .entry(name.to_string())
+                    .or_default()
+                    .entry(biome.to_string())
+                    .or_default() += 1;
+            }
+        },
+    )?;
+
+    match args.format {
+        OutputFormat::Json => println!("{}", serde_json::to_string(&agg)?),
+        OutputFormat::Csv => {
+            println!("kind,block,key,count");
+            for (name, count) in agg.blocks.iter() {
+                println!("total,{name},,{count}");
+            }
+            for (name, ys) in agg.y.iter() {
+                for (y, count) in ys {
+                    println!("y,{name},{y},{count}");
+                }
+            }
+            for (name, chunks) in agg.chunks.iter() {
+                for (chunk, count) in chunks {
+                    println!("chunk,{name},\"{chunk}\",{count}");
+                }
+            }
+            for (name, biomes) in agg.biomes.iter() {
+                for (biome, count) in biomes {
+                    println!("biome,{name},{biome},{count}");
+                }
+            }
+        }
+        OutputFormat::Table => print_tables(&agg),
+    }
+    Ok(())
+}
+
+fn print_tables(agg: &Aggregate) {
+    let width = agg.blocks.keys().map(|k| k.len()).max().unwrap_or(5).max(5);
+    println!("{:width$} {:>10}", "block", "count");
+    for (name, count) in agg.blocks.iter() {
+        println!("{name:width$} {count:>10}");
+    }
+
+    // one row per Y level, one column per block
+    println!();
+    let names: Vec<&String> = agg.y.keys().collect();
+    let ys: BTreeSet<i32> = agg.y.values().flat_map(|ys| ys.keys().copied()).collect();
+    print!("{:>5}", "y");
+    for name in names.iter() {
+        print!(" {:>w$}", name, w = name.len().max(10));
+    }
+    println!();
+    for y in ys.iter().rev() {
+        print!("{y:>5}");
+        for name in names.iter() {
+            let count = agg.y[*name].get(y).copied().unwrap_or(0);
+            print!(" {:>w$}", count, w = name.len().max(10));
+        }
+        println!();
+    }
+
+    for (title, table) in [("chunk", &agg.chunks), ("biome", &agg.biomes)] {
+        if table.is_empty() {
+            continue;
+        }
+        println!();
+        println!("{:width$} {:30} {:>10}", "block", title, "count");
+        for (name, keys) in table.iter() {
+            let mut keys: Vec<_> = keys.iter().collect();
+            keys.sort_by(|a, b| b.1.cmp(a.1));
+            for (key, count) in keys {
+                println!("{name:width$} {key:30} {count:>10}");
+            }
+        }
+    }
+}
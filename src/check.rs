@@ -0,0 +1,219 @@
+use crate::cli::CheckArgs;
+use crate::nbt::Compound;
+use crate::region::{
+    decompress, external_chunk_path, read_chunk_payload, read_header, region_coords, stored_coords,
+    world_region_files, SECTOR_SIZE,
+};
+
+use anyhow::Result;
+use fastnbt::from_bytes;
+use rayon::prelude::*;
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Timestamps further than this in the future are reported
+const CLOCK_SKEW: u64 = 24 * 60 * 60;
+
+#[derive(Serialize, Debug)]
+struct Problem {
+    file: PathBuf,
+    chunk: Option<(i32, i32)>,
+    problem: String,
+    /// harmless oddities that don't make `check` fail
+    warning: bool,
+}
+
+/// How many chunks a file has, and what's wrong with it
+type FileCheck = (usize, Vec<Problem>);
+
+/// Checks every chunk of a region file, returning how many there are and
+/// the problems found.
+fn check_file(path: &Path, now: u64) -> Result<FileCheck> {
+    let mut problems = vec![];
+    let mut problem = |chunk: Option<(i32, i32)>, text: String| {
+        problems.push(Problem {
+            file: path.to_path_buf(),
+            chunk,
+            problem: text,
+            warning: false,
+        })
+    };
+    // older tools, and older versions of mcl, write chunks without a
+    // timestamp; Minecraft loads them fine
+    let mut warnings = vec![];
+
+    let (reg_x, reg_z) = region_coords(path)?;
+    let len = fs::metadata(path)?.len();
+    if len == 0 {
+        // Minecraft leaves these behind, they're harmless
+        return Ok((0, problems));
+    }
+    if len % SECTOR_SIZE != 0 {
+        problem(
+            None,
+            format!("size {len} is not a multiple of {SECTOR_SIZE}"),
+        );
+    }
+
+    let mut file = File::open(path)?;
+    let locations = match read_header(&mut file) {
+        Ok(locations) => locations,
+        Err(e) => {
+            problem(None, e.to_string());
+            return Ok((0, problems));
+        }
+    };
+
+    let file_sectors = len.div_ceil(SECTOR_SIZE);
+    let mut owners: HashMap<u64, (i32, i32)> = HashMap::new();
+    let mut overlaps: HashSet<((i32, i32), (i32, i32))> = HashSet::new();
+
+    for loc in locations.iter() {
+        let x = reg_x * 32 + loc.x as i32;
+        let z = reg_z * 32 + loc.z as i32;
+        let chunk = Some((x, z));
+
+        if loc.sector < 2 {
+            problem(
+                chunk,
+                format!("points into the header, at sector {}", loc.sector),
+            );
+            continue;
+        }
+        if loc.sectors == 0 {
+            problem(chunk, "has an offset but no sectors".to_string());
+            continue;
+        }
+        if loc.sector + loc.sectors > file_sectors {
+            problem(
+                chunk,
+                format!(
+                    "sectors {}..{} are past the end of the file ({file_sectors} sectors)",
+                    loc.sector,
+                    loc.sector + loc.sectors
+                ),
+            );
+            continue;
+        }
+        for sector in loc.sector..loc.sector + loc.sectors {
+            if let Some(other) = owners.insert(sector, (x, z)) {
+                if overlaps.insert((other, (x, z))) {
+                    problem(
+                        chunk,
+                        format!("shares sectors with chunk {} {}", other.0, other.1),
+                    );
+                }
+            }
+        }
+
+        if loc.timestamp == 0 {
+            warnings.push(Problem {
+                file: path.to_path_buf(),
+                chunk,
+                problem: "has no timestamp".to_string(),
+                warning: true,
+            });
+        } else if loc.timestamp as u64 > now + CLOCK_SKEW {
+            problem(
+                chunk,
+                format!("timestamp {} is in the future", loc.timestamp),
+            );
+        }
+
+        let (compression, data) = match read_chunk_payload(&mut file, loc) {
+            Ok(payload) => payload,
+            Err(e) => {
+                problem(chunk, e.to_string());
+                continue;
+            }
+        };
+        let data = if compression >= 128 {
+            let external = external_chunk_path(path, x, z);
+            match fs::read(&external) {
+                Ok(external_data) => decompress(compression - 128, &external_data),
+                Err(e) => {
+                    problem(chunk, format!("reading {}: {e}", external.display()));
+                    continue;
+                }
+            }
+        } else {
+            decompress(compression, &data)
+        };
+        let data = match data {
+            Ok(data) => data,
+            Err(e) => {
+                problem(chunk, format!("decompressing: {e}"));
+                continue;
+            }
+        };
+
+        let compound: Compound = match from_bytes(&data) {
+            Ok(c) => c,
+            Err(e) => {
+                problem(chunk, format!("undecodable NBT: {e}"));
+                continue;
+            }
+        };
+        if let Some((sx, sz)) = stored_coords(&compound) {
+            if (sx, sz) != (x, z) {
+                problem(chunk, format!("says it's chunk {sx} {sz}"));
+            }
+        }
+    }
+
+    problems.extend(warnings);
+    Ok((locations.len(), problems))
+}
+
+/// Checks the region, entities and POI files of a world. Returns whether
+/// everything was fine.
+pub(crate) fn check(args: &CheckArgs) -> Result<bool> {
+    let files = world_region_files(&args.world, args.dimension.as_ref())?;
+    let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+    let results: Vec<(PathBuf, Result<FileCheck>)> = files
+        .into_par_iter()
+        .map(|path| {
+            let result = check_file(&path, now);
+            (path, result)
+        })
+        .collect();
+
+    let mut chunks = 0;
+    let mut problems = vec![];
+    for (path, result) in results {
+        match result {
+            Ok((count, found)) => {
+                chunks += count;
+                problems.extend(found);
+            }
+            Err(e) => problems.push(Problem {
+                file: path,
+                chunk: None,
+                problem: e.to_string(),
+                warning: false,
+            }),
+        }
+    }
+
+    for p in problems.iter() {
+        if args.json {
+            println!("{}", serde_json::to_string(p)?);
+        } else {
+            let warning = if p.warning { "warning: " } else { "" };
+            match p.chunk {
+                Some((x, z)) => {
+                    println!("{}: {warning}chunk {x} {z} {}", p.file.display(), p.problem)
+                }
+                None => println!("{}: {warning}{}", p.file.display(), p.problem),
+            }
+        }
+    }
+    let warnings = problems.iter().filter(|p| p.warning).count();
+    let errors = problems.len() - warnings;
+    log::info!("checked {chunks} chunks, found {errors} problems and {warnings} warnings");
+
+    Ok(errors == 0)
+}
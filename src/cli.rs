@@ -12,6 +12,15 @@ pub struct MclArgs {
     #[arg(short, long, default_value_t = false)]
     pub verbose: bool,
 
+    #[arg(
+        long,
+        global = true,
+        env = "MCL_BACKUP_DIR",
+        default_value = "mcl-backups",
+        help = "where to keep copies of the files modified by a command"
+    )]
+    pub backup_dir: PathBuf,
+
     #[command(subcommand)]
     pub action: Option<Action>,
 }
@@ -23,6 +32,21 @@ pub enum Action {
     Blocks(BlockArgs),
     BlockEntities(BlockEntitiesArgs),
     Entities(EntitiesArgs),
+    Census(CensusArgs),
+    FindItems(FindItemsArgs),
+    Players(PlayersArgs),
+    Level(LevelArgs),
+    Nbt(NbtArgs),
+    Export(ExportArgs),
+    Import(ImportArgs),
+    Check(CheckArgs),
+    Repair(RepairArgs),
+    Compact(CompactArgs),
+    CopyChunks(CopyChunksArgs),
+    RestoreArea(RestoreAreaArgs),
+    Diff(DiffArgs),
+    Render(RenderArgs),
+    Restore(RestoreArgs),
 }
 
 #[derive(Debug, Clone, ValueEnum)]
@@ -54,6 +78,87 @@ pub struct PruneArgs {
         help = "also keep chunks within the given radius of the chunks not pruned"
     )]
     pub buffer: f64,
+
+    #[arg(short, long, value_parser=parse_coords, help = "corner of a box of blocks")]
+    pub from: Option<Coords>,
+
+    #[arg(short, long, value_parser=parse_coords, help = "opposite corner of the box")]
+    pub to: Option<Coords>,
+
+    #[arg(long, value_parser=parse_point, requires = "radius", help = "center (x,z) of a circle")]
+    pub center: Option<(i32, i32)>,
+
+    #[arg(long, requires = "center", help = "radius of the circle, in blocks")]
+    pub radius: Option<f64>,
+
+    #[arg(long, help = "file with the x,z corners of a polygon, one per line")]
+    pub polygon: Option<PathBuf>,
+
+    #[arg(
+        long,
+        help = "file listing chunks and boxes that are never pruned; the buffer extends around them too"
+    )]
+    pub protect: Option<PathBuf>,
+
+    #[arg(
+        long,
+        default_value_t = false,
+        help = "keep chunks with block entities or player placed blocks, as if they were protected"
+    )]
+    pub keep_player_made: bool,
+
+    #[arg(
+        long,
+        requires = "keep_player_made",
+        help = "file with the block ids considered player placed, one per line"
+    )]
+    pub player_blocks: Option<PathBuf>,
+
+    #[arg(
+        long,
+        value_enum,
+        default_value_t = AreaMode::Inside,
+        help = "prune every chunk outside (or inside) the box, circle and polygon"
+    )]
+    pub keep: AreaMode,
+
+    #[arg(
+        long,
+        default_value_t = false,
+        help = "report what would be pruned in each region file without modifying it"
+    )]
+    pub dry_run: bool,
+
+    #[arg(short, long, default_value_t = false, requires = "dry_run")]
+    pub json: bool,
+
+    #[arg(
+        long,
+        default_value_t = false,
+        conflicts_with = "dry_run",
+        help = "compact the pruned region files afterwards, deleting the ones left empty"
+    )]
+    pub compact: bool,
+
+    #[arg(
+        long,
+        requires = "dry_run",
+        help = "with --dry-run, write a PNG map of the chunks kept because they're old, kept for the buffer, and pruned"
+    )]
+    pub preview: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, ValueEnum)]
+pub enum OutputFormat {
+    Table,
+    Csv,
+    Json,
+}
+
+#[derive(Debug, Clone, ValueEnum)]
+pub enum AreaMode {
+    Inside,
+    Outside,
 }
 
 pub type Coords = (i32, i32, i32);
@@ -67,14 +172,51 @@ pub struct BlockArgs {
     #[arg(short, long)]
     pub dimension: Dimension,
 
-    #[arg(short, long)]
-    pub pattern: String,
+    #[arg(
+        short,
+        long,
+        required = true,
+        help = "block id, glob, /regex/ or #tag, optionally followed by [property=value,...]; can be repeated"
+    )]
+    pub pattern: Vec<String>,
+
+    #[arg(
+        long,
+        help = "data directory to read block tags from, as found in datapacks"
+    )]
+    pub tags: Option<PathBuf>,
 
     #[arg(short, long, value_parser=parse_coords)]
-    pub from: Coords,
+    pub from: Option<Coords>,
 
     #[arg(short, long, value_parser=parse_coords)]
-    pub to: Coords,
+    pub to: Option<Coords>,
+
+    #[arg(
+        long,
+        default_value_t = false,
+        help = "count the blocks per id and Y level instead of listing them"
+    )]
+    pub aggregate: bool,
+
+    #[arg(
+        long,
+        default_value_t = false,
+        requires = "aggregate",
+        help = "also count them per chunk"
+    )]
+    pub per_chunk: bool,
+
+    #[arg(
+        long,
+        default_value_t = false,
+        requires = "aggregate",
+        help = "also count them per biome"
+    )]
+    pub per_biome: bool,
+
+    #[arg(long, value_enum, default_value_t = OutputFormat::Table)]
+    pub format: OutputFormat,
 }
 
 #[derive(Args, Debug)]
@@ -113,9 +255,140 @@ pub struct EntitiesArgs {
 
     #[arg(short, long, default_value_t = false)]
     pub json: bool,
+
+    #[arg(long, help = "entity id, glob or /regex/")]
+    pub id: Option<String>,
+
+    #[arg(short, long, help = "custom name, glob or /regex/")]
+    pub name: Option<String>,
+
+    #[arg(short, long)]
+    pub uuid: Option<String>,
+}
+
+#[derive(Args, Debug)]
+#[command(arg_required_else_help = true)]
+pub struct CensusArgs {
+    #[arg(short, long)]
+    pub world: PathBuf,
+
+    #[arg(short, long)]
+    pub dimension: Dimension,
+
+    #[arg(short, long, value_parser=parse_coords)]
+    pub from: Option<Coords>,
+
+    #[arg(short, long, value_parser=parse_coords)]
+    pub to: Option<Coords>,
+
+    #[arg(long, help = "only count entities with this id, glob or /regex/")]
+    pub id: Option<String>,
+
+    #[arg(long, help = "flag chunks with more entities than this")]
+    pub threshold: Option<u64>,
+
+    #[arg(
+        long,
+        default_value_t = 20,
+        help = "how many chunks and regions to list, 0 for all"
+    )]
+    pub top: usize,
+
+    #[arg(long, value_enum, default_value_t = OutputFormat::Table)]
+    pub format: OutputFormat,
+}
+
+#[derive(Args, Debug)]
+#[command(arg_required_else_help = true)]
+pub struct FindItemsArgs {
+    #[arg(short, long)]
+    pub world: PathBuf,
+
+    #[arg(short, long)]
+    pub dimension: Dimension,
+
+    #[arg(short, long, value_parser=parse_coords)]
+    pub from: Option<Coords>,
+
+    #[arg(short, long, value_parser=parse_coords)]
+    pub to: Option<Coords>,
+
+    #[arg(short, long, help = "item id, glob or /regex/")]
+    pub item: Option<String>,
+
+    #[arg(long, default_value_t = false, help = "only look into block entities")]
+    pub no_entities: bool,
+
+    #[arg(short, long, default_value_t = false)]
+    pub json: bool,
 }
 
-fn parse_coords(coords: &str) -> Result<Coords> {
+#[derive(Args, Debug)]
+#[command(arg_required_else_help = true)]
+pub struct PlayersArgs {
+    #[arg(short, long)]
+    pub world: PathBuf,
+
+    #[arg(short, long, help = "name or UUID of the player to show")]
+    pub player: Option<String>,
+
+    #[arg(
+        short,
+        long,
+        default_value_t = false,
+        help = "include inventories and ender chests"
+    )]
+    pub items: bool,
+
+    #[arg(short, long, default_value_t = false)]
+    pub json: bool,
+}
+
+#[derive(Debug, Clone, ValueEnum)]
+pub enum Difficulty {
+    Peaceful,
+    Easy,
+    Normal,
+    Hard,
+}
+
+#[derive(Args, Debug)]
+#[command(arg_required_else_help = true)]
+pub struct LevelArgs {
+    #[arg(short, long)]
+    pub world: PathBuf,
+
+    #[arg(short, long, default_value_t = false)]
+    pub json: bool,
+
+    #[arg(
+        long,
+        help = "set a game rule, as in doDaylightCycle=false; can be repeated"
+    )]
+    pub gamerule: Vec<String>,
+
+    #[arg(
+        long,
+        default_value_t = false,
+        requires = "gamerule",
+        help = "let --gamerule add rules the world doesn't have yet"
+    )]
+    pub force: bool,
+
+    #[arg(long, value_parser=parse_coords, help = "set the world spawn")]
+    pub spawn: Option<Coords>,
+
+    #[arg(long, value_enum)]
+    pub difficulty: Option<Difficulty>,
+
+    #[arg(long, help = "set the world border size, in blocks")]
+    pub border_size: Option<f64>,
+
+    #[arg(long, value_parser=parse_point, help = "set the x,z center of the world border")]
+    pub border_center: Option<(i32, i32)>,
+}
+
+pub(crate) fn parse_coords(coords: &str) -> Result<Coords> {
     let v: Result<Vec<i32>, ParseIntError> =
         coords.splitn(3, ',').map(|s| s.parse::<i32>()).collect();
     let v = v?;
@@ -125,11 +398,329 @@ fn parse_coords(coords: &str) -> Result<Coords> {
     bail!(format!("Failed to parse coordinates {}", coords))
 }
 
+pub(crate) fn parse_point(point: &str) -> Result<(i32, i32)> {
+    let v: Result<Vec<i32>, ParseIntError> =
+        point.splitn(2, ',').map(|s| s.parse::<i32>()).collect();
+    let v = v?;
+    if v.len() == 2 {
+        return Ok((v[0], v[1]));
+    }
+    bail!(format!("Failed to parse point {}", point))
+}
+
 #[derive(Args, Debug)]
 #[command(arg_required_else_help = true)]
 pub struct ResetLightingArgs {
-    #[arg()]
-    pub region: String,
+    #[command(flatten)]
+    pub chunks: ChunkArgs,
+}
+
+/// Chunks to work on: a single region file, or a whole dimension, optionally
+/// limited to the chunks between `--from` and `--to`.
+#[derive(Args, Debug)]
+pub struct ChunkArgs {
+    #[arg(
+        required_unless_present = "world",
+        conflicts_with = "world",
+        help = "a single region file"
+    )]
+    pub region: Option<PathBuf>,
+
+    #[arg(short, long, requires = "dimension")]
+    pub world: Option<PathBuf>,
+
+    #[arg(short, long)]
+    pub dimension: Option<Dimension>,
+
+    #[arg(short, long, value_parser=parse_coords)]
+    pub from: Option<Coords>,
+
+    #[arg(short, long, value_parser=parse_coords)]
+    pub to: Option<Coords>,
+}
+
+#[derive(Args, Debug)]
+#[command(arg_required_else_help = true)]
+pub struct NbtArgs {
+    #[command(subcommand)]
+    pub action: NbtAction,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum NbtAction {
+    /// Print the tags a path points at, as SNBT
+    Get(NbtGetArgs),
+    /// Replace, or create, the tags a path points at
+    Set(NbtSetArgs),
+    /// Remove the tags a path points at
+    Remove(NbtRemoveArgs),
+}
+
+#[derive(Args, Debug)]
+#[command(arg_required_else_help = true)]
+pub struct NbtGetArgs {
+    #[command(flatten)]
+    pub chunks: ChunkArgs,
+
+    #[arg(
+        short,
+        long,
+        help = r#"NBT path, as in block_entities[{id:"minecraft:chest"}].Items"#
+    )]
+    pub path: String,
+}
+
+#[derive(Args, Debug)]
+#[command(arg_required_else_help = true)]
+pub struct NbtSetArgs {
+    #[command(flatten)]
+    pub chunks: ChunkArgs,
+
+    #[arg(
+        short,
+        long,
+        help = r#"NBT path, as in block_entities[{id:"minecraft:chest"}].Items"#
+    )]
+    pub path: String,
+
+    #[arg(short, long, help = "new value, as SNBT: 1b, 2.5d, \"text\", {a:1}")]
+    pub value: String,
+}
+
+#[derive(Args, Debug)]
+#[command(arg_required_else_help = true)]
+pub struct NbtRemoveArgs {
+    #[command(flatten)]
+    pub chunks: ChunkArgs,
+
+    #[arg(
+        short,
+        long,
+        help = r#"NBT path, as in block_entities[{id:"minecraft:chest"}].Items"#
+    )]
+    pub path: String,
+}
+
+#[derive(Debug, Clone, ValueEnum)]
+pub enum ExportKind {
+    Chunks,
+    BlockEntities,
+    Entities,
+}
+
+#[derive(Debug, Clone, ValueEnum)]
+pub enum NbtFormat {
+    Snbt,
+    Json,
+}
+
+#[derive(Args, Debug)]
+#[command(arg_required_else_help = true)]
+pub struct ExportArgs {
+    #[arg(short, long)]
+    pub world: PathBuf,
+
+    #[arg(short, long)]
+    pub dimension: Dimension,
+
+    #[arg(short, long, value_parser=parse_coords)]
+    pub from: Option<Coords>,
+
+    #[arg(short, long, value_parser=parse_coords)]
+    pub to: Option<Coords>,
+
+    #[arg(short, long, value_enum, default_value_t = ExportKind::Chunks)]
+    pub kind: ExportKind,
+
+    #[arg(long, value_enum, default_value_t = NbtFormat::Snbt)]
+    pub format: NbtFormat,
+
+    #[arg(short, long, help = "file to write to, instead of stdout")]
+    pub output: Option<PathBuf>,
+}
+
+#[derive(Args, Debug)]
+#[command(arg_required_else_help = true)]
+pub struct ImportArgs {
+    #[arg(help = "file written by export, stdin if missing")]
+    pub input: Option<PathBuf>,
+
+    #[arg(short, long)]
+    pub world: PathBuf,
+
+    #[arg(short, long)]
+    pub dimension: Dimension,
+
+    #[arg(short, long, value_enum, default_value_t = ExportKind::Chunks)]
+    pub kind: ExportKind,
+
+    #[arg(long, value_enum, default_value_t = NbtFormat::Snbt)]
+    pub format: NbtFormat,
+}
+
+#[derive(Args, Debug)]
+#[command(arg_required_else_help = true)]
+pub struct CheckArgs {
+    #[arg(short, long)]
+    pub world: PathBuf,
+
+    #[arg(
+        short,
+        long,
+        help = "only check this dimension, instead of all of them"
+    )]
+    pub dimension: Option<Dimension>,
+
+    #[arg(short, long, default_value_t = false)]
+    pub json: bool,
+}
+
+#[derive(Args, Debug)]
+#[command(arg_required_else_help = true)]
+pub struct RepairArgs {
+    #[arg(short, long)]
+    pub world: PathBuf,
+
+    #[arg(
+        short,
+        long,
+        help = "only repair this dimension, instead of all of them"
+    )]
+    pub dimension: Option<Dimension>,
+
+    #[arg(long, default_value_t = false)]
+    pub dry_run: bool,
+}
+
+#[derive(Args, Debug)]
+#[command(arg_required_else_help = true)]
+pub struct CompactArgs {
+    #[arg(short, long)]
+    pub world: PathBuf,
+
+    #[arg(
+        short,
+        long,
+        help = "only compact this dimension, instead of all of them"
+    )]
+    pub dimension: Option<Dimension>,
+
+    #[arg(long, default_value_t = false)]
+    pub dry_run: bool,
+}
+
+#[derive(Args, Debug)]
+#[command(arg_required_else_help = true)]
+pub struct CopyChunksArgs {
+    #[arg(long, help = "world to copy from")]
+    pub from_world: PathBuf,
+
+    #[arg(
+        long,
+        help = "dimension to copy from, the same as --dimension if missing"
+    )]
+    pub from_dimension: Option<Dimension>,
+
+    #[arg(short, long, help = "world to copy to")]
+    pub world: PathBuf,
+
+    #[arg(short, long, help = "dimension to copy to")]
+    pub dimension: Dimension,
+
+    #[arg(short, long, value_parser=parse_coords)]
+    pub from: Option<Coords>,
+
+    #[arg(short, long, value_parser=parse_coords)]
+    pub to: Option<Coords>,
+
+    #[arg(long, value_parser=parse_point, help = "x,z chunks to move the copy by")]
+    pub offset: Option<(i32, i32)>,
+}
+
+#[derive(Args, Debug)]
+#[command(arg_required_else_help = true)]
+pub struct RestoreAreaArgs {
+    #[arg(long, help = "backup of the world to take the chunks from")]
+    pub from_world: PathBuf,
+
+    #[arg(short, long, help = "live world to restore the chunks into")]
+    pub world: PathBuf,
+
+    #[arg(short, long)]
+    pub dimension: Dimension,
+
+    #[arg(short, long, value_parser=parse_coords)]
+    pub from: Coords,
+
+    #[arg(short, long, value_parser=parse_coords)]
+    pub to: Coords,
+}
+
+#[derive(Args, Debug)]
+#[command(arg_required_else_help = true)]
+pub struct DiffArgs {
+    #[arg(help = "world as it was, like a backup")]
+    pub world_a: PathBuf,
+
+    #[arg(help = "world as it is now")]
+    pub world_b: PathBuf,
+
+    #[arg(short, long)]
+    pub dimension: Dimension,
+
+    #[arg(short, long, value_parser=parse_coords)]
+    pub from: Option<Coords>,
+
+    #[arg(short, long, value_parser=parse_coords)]
+    pub to: Option<Coords>,
+
+    #[arg(short, long, default_value_t = false)]
+    pub json: bool,
+}
+
+#[derive(Args, Debug)]
+#[command(arg_required_else_help = true)]
+pub struct RenderArgs {
+    #[arg(short, long)]
+    pub world: PathBuf,
+
+    #[arg(short, long)]
+    pub dimension: Dimension,
+
+    #[arg(short, long, value_parser=parse_coords, help = "corner of the area to render, y is ignored")]
+    pub from: Option<Coords>,
+
+    #[arg(short, long, value_parser=parse_coords, help = "corner of the area to render, y is ignored")]
+    pub to: Option<Coords>,
+
+    #[arg(short, long, help = "PNG file to write")]
+    pub output: PathBuf,
+
+    #[arg(short, long, help = "JSON file mapping block ids to colors")]
+    pub palette: Option<PathBuf>,
+
+    #[arg(
+        short,
+        long,
+        default_value_t = 1,
+        value_parser = clap::value_parser!(u32).range(1..=16),
+        help = "pixels per block"
+    )]
+    pub scale: u32,
+
+    #[arg(
+        long,
+        default_value_t = false,
+        help = "overlay a heatmap of InhabitedTime"
+    )]
+    pub heatmap: bool,
+}
+
+#[derive(Args, Debug)]
+#[command(arg_required_else_help = true)]
+pub struct RestoreArgs {
+    #[arg(help = "journal written by a previous command, or the backup directory holding it")]
+    pub journal: PathBuf,
 }
 
 #[cfg(test)]
@@ -141,4 +732,51 @@ mod tests {
     fn clap_check() {
         MclArgs::command().debug_assert();
     }
+
+    #[test]
+    fn blocks_aggregate() {
+        let args = MclArgs::try_parse_from([
+            "mcl",
+            "blocks",
+            "--world",
+            "w",
+            "--dimension",
+            "overworld",
+            "--pattern",
+            "diamond_ore",
+            "--aggregate",
+            "--per-chunk",
+            "--format",
+            "csv",
+        ])
+        .unwrap();
+        let Some(Action::Blocks(blocks)) = args.action else {
+            panic!("expected the blocks command");
+        };
+        assert!(blocks.aggregate);
+        assert!(blocks.per_chunk);
+        assert!(!blocks.per_biome);
+        assert!(matches!(blocks.format, OutputFormat::Csv));
+    }
+
+    #[test]
+    fn prune_preview_needs_dry_run() {
+        let prune = [
+            "mcl",
+            "prune",
+            "-w",
+            "w",
+            "-d",
+            "overworld",
+            "-i",
+            "100",
+            "-b",
+            "2",
+        ];
+        let preview = ["--preview", "map.png"];
+        assert!(MclArgs::try_parse_from(prune.iter().chain(&preview)).is_err());
+        assert!(
+            MclArgs::try_parse_from(prune.iter().chain(&preview).chain(&["--dry-run"])).is_ok()
+        );
+    }
 }
@@ -0,0 +1,113 @@
+use crate::backup::Backup;
+use crate::cli::CompactArgs;
+use crate::region::{read_stored_chunks, world_region_files, write_region, SECTOR_SIZE};
+
+use anyhow::Result;
+use rayon::prelude::*;
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+
+/// Sizes of a region file before and after compacting it; `after` is
+/// `None` when the file had no chunks left and was deleted.
+struct Compaction {
+    file: PathBuf,
+    before: u64,
+    after: Option<u64>,
+}
+
+impl Compaction {
+    fn reclaimed(&self) -> u64 {
+        self.before - self.after.unwrap_or(0)
+    }
+}
+
+/// Rewrites a region file so its chunks are back to back, or deletes it if
+/// it has no chunks. Returns `None` when there's nothing to reclaim.
+fn compact_file(path: &Path, dry_run: bool, backup: &Backup) -> Result<Option<Compaction>> {
+    let before = fs::metadata(path)?.len();
+    let (chunks, failed) = if before == 0 {
+        (vec![], vec![])
+    } else {
+        read_stored_chunks(&mut File::open(path)?)?
+    };
+    if !failed.is_empty() {
+        log::warn!(
+            "{} has {} chunks that can't be read, skipping it; `mcl repair` can fix it",
+            path.display(),
+            failed.len()
+        );
+        return Ok(None);
+    }
+
+    if chunks.is_empty() {
+        if !dry_run {
+            backup.save(path)?;
+            fs::remove_file(path)?;
+            backup.record(path, "deleted, no chunks left".to_string())?;
+        }
+        return Ok(Some(Compaction {
+            file: path.to_path_buf(),
+            before,
+            after: None,
+        }));
+    }
+
+    let after = (2 + chunks.iter().map(|c| c.sectors()).sum::<u64>()) * SECTOR_SIZE;
+    if after >= before {
+        return Ok(None);
+    }
+    if !dry_run {
+        backup.save(path)?;
+        write_region(path, &chunks)?;
+        backup.record(
+            path,
+            format!("compacted, {} bytes reclaimed", before - after),
+        )?;
+    }
+    Ok(Some(Compaction {
+        file: path.to_path_buf(),
+        before,
+        after: Some(after),
+    }))
+}
+
+/// Compacts region files in parallel, printing what was reclaimed in each
+/// and in total. Returns the total.
+pub(crate) fn compact_files(files: &[PathBuf], dry_run: bool, backup: &Backup) -> Result<u64> {
+    let compactions: Result<Vec<Option<Compaction>>> = files
+        .par_iter()
+        .map(|path| compact_file(path, dry_run, backup))
+        .collect();
+
+    let mut total = 0;
+    for c in compactions?.into_iter().flatten() {
+        match c.after {
+            Some(after) => println!(
+                "{}: {} -> {} bytes, {} reclaimed",
+                c.file.display(),
+                c.before,
+                after,
+                c.reclaimed()
+            ),
+            None => println!(
+                "{}: no chunks left, deleted, {} bytes reclaimed",
+                c.file.display(),
+                c.before
+            ),
+        }
+        total += c.reclaimed();
+    }
+    let verb = if dry_run {
+        "would be reclaimed"
+    } else {
+        "reclaimed"
+    };
+    println!("{total} bytes {verb} in total");
+    Ok(total)
+}
+
+pub(crate) fn compact(args: &CompactArgs, backup: &Backup) -> Result<()> {
+    let files = world_region_files(&args.world, args.dimension.as_ref())?;
+    compact_files(&files, args.dry_run, backup)?;
+    Ok(())
+}
@@ -0,0 +1,683 @@
+use crate::backup::Backup;
+use crate::cli::{Coords, CopyChunksArgs, Dimension, RestoreAreaArgs};
+use crate::nbt::Compound;
+use crate::prune::list_region_files;
+use crate::region::{close_region, dimension_dir, for_each_chunk, open_region_for_writing};
+
+use anyhow::{bail, Result};
+use fastanvil::Region;
+use fastnbt::{to_bytes, IntArray, Value};
+use std::collections::hash_map::RandomState;
+use std::collections::{BTreeMap, HashSet};
+use std::fs::{self, File};
+use std::hash::{BuildHasher, Hasher};
+use std::path::{Path, PathBuf};
+
+/// The three kinds of region files a dimension has
+pub(crate) const REGION_KINDS: [&str; 3] = ["region", "entities", "poi"];
+
+/// Chunks copied of each kind, by their coordinates in the destination
+pub(crate) type Copied = BTreeMap<&'static str, HashSet<(i32, i32)>>;
+
+fn shift_int(c: &mut Compound, key: &str, by: i32) {
+    if let Some(Value::Int(n)) = c.get_mut(key) {
+        *n += by;
+    }
+}
+
+/// Adds `shifts` to the given elements of an int array
+fn shift_int_array(c: &mut Compound, key: &str, shifts: &[(usize, i32)]) {
+    let Some(Value::IntArray(a)) = c.get(key) else {
+        return;
+    };
+    let mut values = a.to_vec();
+    for (i, by) in shifts {
+        if let Some(n) = values.get_mut(*i) {
+            *n += by;
+        }
+    }
+    c.insert(key.to_string(), Value::IntArray(IntArray::new(values)));
+}
+
+fn compounds<'a>(c: &'a mut Compound, key: &str) -> impl Iterator<Item = &'a mut Compound> {
+    let items = match c.get_mut(key) {
+        Some(Value::List(items)) => items.as_mut_slice(),
+        _ => &mut [],
+    };
+    items.iter_mut().filter_map(|item| match item {
+        Value::Compound(c) => Some(c),
+        _ => None,
+    })
+}
+
+/// Shifts the x and z of the block position kept under `key`, either as an
+/// int array or as a compound with `X`, `Y` and `Z`
+fn shift_block_pos(c: &mut Compound, key: &str, dx: i32, dz: i32) {
+    if let Some(Value::Compound(pos)) = c.get_mut(key) {
+        shift_int(pos, "X", dx * 16);
+        shift_int(pos, "Z", dz * 16);
+    } else {
+        shift_int_array(c, key, &[(0, dx * 16), (2, dz * 16)]);
+    }
+}
+
+/// Moves a terrain chunk by `dx`, `dz` chunks: its position, block
+/// entities and scheduled ticks. Structure references would point at the
+/// wrong place, so they're dropped. Chunks from before 1.18 keep all this
+/// under `Level`, along with their entities.
+fn shift_terrain(chunk: &mut Compound, dx: i32, dz: i32, uuids: Option<&UuidSource>) {
+    let moved = (dx, dz) != (0, 0);
+    let (chunk, lists, structures, starts) = match chunk.get_mut("Level") {
+        Some(Value::Compound(level)) => (
+            level,
+            ["TileEntities", "TileTicks", "LiquidTicks"],
+            "Structures",
+            "Starts",
+        ),
+        _ => (
+            chunk,
+            ["block_entities", "block_ticks", "fluid_ticks"],
+            "structures",
+            "starts",
+        ),
+    };
+    for entity in compounds(chunk, "Entities") {
+        shift_entity(entity, dx, dz, uuids);
+    }
+    if !moved {
+        return;
+    }
+    shift_int(chunk, "xPos", dx);
+    shift_int(chunk, "zPos", dz);
+    for key in lists {
+        for item in compounds(chunk, key) {
+            shift_int(item, "x", dx * 16);
+            shift_int(item, "z", dz * 16);
+        }
+    }
+    if let Some(Value::Compound(structures)) = chunk.get_mut(structures) {
+        structures.insert("References".to_string(), Value::Compound(Compound::new()));
+        structures.insert(starts.to_string(), Value::Compound(Compound::new()));
+    }
+}
+
+/// Source of new entity UUIDs, for copies within the same world where the
+/// originals would clash. Each old UUID always gets the same new one.
+struct UuidSource(RandomState);
+
+impl UuidSource {
+    fn next(&self, old: &[i32]) -> Value {
+        let mut hasher = self.0.build_hasher();
+        let mut uuid = vec![];
+        for part in old {
+            hasher.write_i32(*part);
+            let n = hasher.finish();
+            uuid.push(n as i32);
+        }
+        Value::IntArray(IntArray::new(uuid))
+    }
+}
+
+/// Block positions entities remember, by their names since 1.20.5 and
+/// before it
+const ENTITY_POSITIONS: [&str; 19] = [
+    "sleeping_pos",
+    "home_pos",
+    "HomePos",
+    "travel_pos",
+    "bound_pos",
+    "anchor_pos",
+    "treasure_pos",
+    "hive_pos",
+    "HivePos",
+    "flower_pos",
+    "FlowerPos",
+    "wander_target",
+    "WanderTarget",
+    "patrol_target",
+    "PatrolTarget",
+    "beam_target",
+    "BeamTarget",
+    "leash",
+    "Leash",
+];
+
+/// Block positions older versions kept as separate x and z ints
+const ENTITY_POSITION_INTS: [(&str, &str); 6] = [
+    ("SleepingX", "SleepingZ"),
+    ("HomePosX", "HomePosZ"),
+    ("TravelPosX", "TravelPosZ"),
+    ("BoundX", "BoundZ"),
+    ("AX", "AZ"),
+    ("TreasurePosX", "TreasurePosZ"),
+];
+
+fn shift_entity(entity: &mut Compound, dx: i32, dz: i32, uuids: Option<&UuidSource>) {
+    if let Some(Value::List(pos)) = entity.get_mut("Pos") {
+        if let [Value::Double(x), _, Value::Double(z)] = pos.as_mut_slice() {
+            *x += (dx * 16) as f64;
+            *z += (dz * 16) as f64;
+        }
+    }
+    // hanging entities like paintings and item frames
+    shift_int(entity, "TileX", dx * 16);
+    shift_int(entity, "TileZ", dz * 16);
+    shift_block_pos(entity, "block_pos", dx, dz);
+    for key in ENTITY_POSITIONS {
+        shift_block_pos(entity, key, dx, dz);
+    }
+    for (x, z) in ENTITY_POSITION_INTS {
+        shift_int(entity, x, dx * 16);
+        shift_int(entity, z, dz * 16);
+    }
+    // villagers' home, job site and meeting point
+    if let Some(Value::Compound(brain)) = entity.get_mut("Brain") {
+        if let Some(Value::Compound(memories)) = brain.get_mut("memories") {
+            for memory in memories.values_mut() {
+                if let Value::Compound(memory) = memory {
+                    if let Some(Value::Compound(value)) = memory.get_mut("value") {
+                        shift_block_pos(value, "pos", dx, dz);
+                    }
+                }
+            }
+        }
+    }
+
+    if let Some(uuids) = uuids {
+        if let Some(Value::IntArray(old)) = entity.get("UUID") {
+            let uuid = uuids.next(old);
+            entity.insert("UUID".to_string(), uuid);
+        }
+    }
+    for passenger in compounds(entity, "Passengers") {
+        shift_entity(passenger, dx, dz, uuids);
+    }
+}
+
+fn shift_entities(chunk: &mut Compound, dx: i32, dz: i32, uuids: Option<&UuidSource>) {
+    shift_int_array(chunk, "Position", &[(0, dx), (1, dz)]);
+    for entity in compounds(chunk, "Entities") {
+        shift_entity(entity, dx, dz, uuids);
+    }
+}
+
+/// Moves the points of interest of a chunk, kept by section in `Sections`
+fn shift_poi(chunk: &mut Compound, dx: i32, dz: i32) {
+    let Some(Value::Compound(sections)) = chunk.get_mut("Sections") else {
+        return;
+    };
+    for section in sections.values_mut() {
+        let Value::Compound(section) = section else {
+            continue;
+        };
+        for record in compounds(section, "Records") {
+            shift_int_array(record, "pos", &[(0, dx * 16), (2, dz * 16)]);
+        }
+    }
+}
+
+/// A chunk's coordinates and its serialized NBT
+type SerializedChunk = (i32, i32, Vec<u8>);
+
+/// Writes serialized chunks into their slots of a region file, backing it
+/// up first.
+fn write_chunks(
+    path: &Path,
+    chunks: &[SerializedChunk],
+    backup: &Backup,
+    change: String,
+) -> Result<()> {
+    backup.save(path)?;
+    let mut reg = open_region_for_writing(path)?;
+    let mut written = vec![];
+    for (x, z, data) in chunks {
+        let slot = (x.rem_euclid(32) as usize, z.rem_euclid(32) as usize);
+        reg.write_chunk(slot.0, slot.1, data)?;
+        written.push(slot);
+    }
+    close_region(reg, &written)?;
+    backup.record(path, change)
+}
+
+/// Whether two paths lead to the same existing file or directory
+fn same_path(a: &Path, b: &Path) -> bool {
+    match (fs::canonicalize(a), fs::canonicalize(b)) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => false,
+    }
+}
+
+/// What to copy, and where to
+pub(crate) struct CopySpec<'a> {
+    pub(crate) source: &'a Path,
+    pub(crate) source_dimension: &'a Dimension,
+    pub(crate) world: &'a Path,
+    pub(crate) dimension: &'a Dimension,
+    pub(crate) from: Option<&'a Coords>,
+    pub(crate) to: Option<&'a Coords>,
+    /// in chunks
+    pub(crate) offset: (i32, i32),
+}
+
+/// Copies the terrain, entities and POI chunks within the bounds, one
+/// source region file at a time. Returns the chunks copied.
+pub(crate) fn copy_chunks(spec: &CopySpec, backup: &Backup) -> Result<Copied> {
+    let (dx, dz) = spec.offset;
+    // entity UUIDs must be unique across the whole world
+    let same_world = same_path(spec.source, spec.world);
+    let uuids = same_world.then(|| UuidSource(RandomState::new()));
+    let source_name = spec.source.display().to_string();
+
+    let mut copied = Copied::new();
+    for kind in REGION_KINDS {
+        let source_dir = dimension_dir(spec.source, spec.source_dimension).join(kind);
+        if !source_dir.is_dir() {
+            log::debug!("{} doesn't exist, skipping", source_dir.display());
+            continue;
+        }
+        let dest_dir = dimension_dir(spec.world, spec.dimension).join(kind);
+        let kind_copied = copied.entry(kind).or_default();
+
+        for source_file in list_region_files(&source_dir)? {
+            // chunks of this source region, by destination region
+            let mut regions: BTreeMap<(i32, i32), Vec<SerializedChunk>> = BTreeMap::new();
+            for_each_chunk(
+                std::slice::from_ref(&source_file),
+                spec.from,
+                spec.to,
+                |x, z, chunk| {
+                    let (x, z) = (x + dx, z + dz);
+                    let mut chunk = chunk.clone();
+                    let moved = (dx, dz) != (0, 0);
+                    match kind {
+                        "region" if moved || uuids.is_some() => {
+                            shift_terrain(&mut chunk, dx, dz, uuids.as_ref())
+                        }
+                        "entities" if moved || uuids.is_some() => {
+                            shift_entities(&mut chunk, dx, dz, uuids.as_ref())
+                        }
+                        "poi" if moved => shift_poi(&mut chunk, dx, dz),
+                        _ => {}
+                    }
+                    regions
+                        .entry((x >> 5, z >> 5))
+                        .or_default()
+                        .push((x, z, to_bytes(&chunk)?));
+                    Ok(())
+                },
+            )?;
+
+            for ((reg_x, reg_z), chunks) in regions {
+                let path: PathBuf = dest_dir.join(format!("r.{reg_x}.{reg_z}.mca"));
+                write_chunks(
+                    &path,
+                    &chunks,
+                    backup,
+                    format!("copied {} chunks from {source_name}", chunks.len()),
+                )?;
+                kind_copied.extend(chunks.iter().map(|(x, z, _)| (*x, *z)));
+            }
+        }
+        log::info!("copied {} {kind} chunks", kind_copied.len());
+    }
+
+    // entities and points of interest the source doesn't have must go too,
+    // or they'd be left on top of the copied terrain
+    let none = HashSet::new();
+    let terrain = copied.get("region").unwrap_or(&none);
+    for kind in ["entities", "poi"] {
+        let kind_copied = copied.get(kind).unwrap_or(&none);
+        let mut regions: BTreeMap<(i32, i32), Vec<(i32, i32)>> = BTreeMap::new();
+        for (x, z) in terrain.difference(kind_copied) {
+            regions.entry((x >> 5, z >> 5)).or_default().push((*x, *z));
+        }
+
+        let dir = dimension_dir(spec.world, spec.dimension).join(kind);
+        let mut removed = 0;
+        for ((reg_x, reg_z), chunks) in regions {
+            let path = dir.join(format!("r.{reg_x}.{reg_z}.mca"));
+            let present = present_chunks(&path, &chunks)?;
+            if present.is_empty() {
+                continue;
+            }
+            remove_live_chunks(
+                &path,
+                &present,
+                backup,
+                format!(
+                    "removed {} chunks missing from {source_name}",
+                    present.len()
+                ),
+            )?;
+            removed += present.len();
+        }
+        log::info!("removed {removed} {kind} chunks missing from the source");
+    }
+    Ok(copied)
+}
+
+/// Chunk rectangle covered by a block box
+fn chunk_box(from: &Coords, to: &Coords) -> ((i32, i32), (i32, i32)) {
+    (
+        (from.0.min(to.0) >> 4, from.2.min(to.2) >> 4),
+        (from.0.max(to.0) >> 4, from.2.max(to.2) >> 4),
+    )
+}
+
+pub(crate) fn copy_chunks_command(args: &CopyChunksArgs, backup: &Backup) -> Result<()> {
+    let source_dimension = args.from_dimension.as_ref().unwrap_or(&args.dimension);
+    let offset = args.offset.unwrap_or((0, 0));
+
+    if same_path(
+        &dimension_dir(&args.from_world, source_dimension),
+        &dimension_dir(&args.world, &args.dimension),
+    ) {
+        // copying in place would read chunks already overwritten
+        let (Some(from), Some(to)) = (&args.from, &args.to) else {
+            bail!("copying within the same dimension needs both --from and --to");
+        };
+        let (f, t) = chunk_box(from, to);
+        let (dx, dz) = offset;
+        if f.0 + dx <= t.0 && f.0 <= t.0 + dx && f.1 + dz <= t.1 && f.1 <= t.1 + dz {
+            bail!("the destination overlaps the source, use an offset that moves it clear");
+        }
+    }
+
+    copy_chunks(
+        &CopySpec {
+            source: &args.from_world,
+            source_dimension,
+            world: &args.world,
+            dimension: &args.dimension,
+            from: args.from.as_ref(),
+            to: args.to.as_ref(),
+            offset,
+        },
+        backup,
+    )?;
+    Ok(())
+}
+
+/// Removes chunks from a live region file, backing it up first
+fn remove_live_chunks(
+    path: &Path,
+    chunks: &[(i32, i32)],
+    backup: &Backup,
+    change: String,
+) -> Result<()> {
+    backup.save(path)?;
+    let file = File::options().read(true).write(true).open(path)?;
+    let mut reg = Region::from_stream(file)?;
+    for (x, z) in chunks {
+        reg.remove_chunk(x.rem_euclid(32) as usize, z.rem_euclid(32) as usize)?;
+    }
+    close_region(reg, &[])?;
+    backup.record(path, change)
+}
+
+/// Which of `chunks` a region file has
+fn present_chunks(path: &Path, chunks: &[(i32, i32)]) -> Result<Vec<(i32, i32)>> {
+    if !path.is_file() || fs::metadata(path)?.len() == 0 {
+        return Ok(vec![]);
+    }
+    let mut reg = Region::from_stream(File::open(path)?)?;
+    let mut present = vec![];
+    for (x, z) in chunks {
+        if reg
+            .read_chunk(x.rem_euclid(32) as usize, z.rem_euclid(32) as usize)?
+            .is_some()
+        {
+            present.push((*x, *z));
+        }
+    }
+    Ok(present)
+}
+
+pub(crate) fn restore_area(args: &RestoreAreaArgs, backup: &Backup) -> Result<()> {
+    if same_path(&args.from_world, &args.world) {
+        bail!("--from-world and --world are the same world");
+    }
+    let copied = copy_chunks(
+        &CopySpec {
+            source: &args.from_world,
+            source_dimension: &args.dimension,
+            world: &args.world,
+            dimension: &args.dimension,
+            from: Some(&args.from),
+            to: Some(&args.to),
+            offset: (0, 0),
+        },
+        backup,
+    )?;
+
+    let none = HashSet::new();
+    let restored = copied.get("region").unwrap_or(&none);
+    let (f, t) = chunk_box(&args.from, &args.to);
+    let mut missing = 0;
+    for z in f.1..=t.1 {
+        for x in f.0..=t.0 {
+            if !restored.contains(&(x, z)) {
+                println!("chunk {x} {z} is missing from the backup, left as it is");
+                missing += 1;
+            }
+        }
+    }
+
+    log::info!(
+        "restored {} chunks, {missing} missing from the backup",
+        restored.len()
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::snbt;
+
+    fn compound(text: &str) -> Compound {
+        let Value::Compound(c) = snbt::parse(text).unwrap() else {
+            panic!("expected a compound");
+        };
+        c
+    }
+
+    #[test]
+    fn shifting() {
+        let mut chunk = compound(
+            r#"{xPos:1,zPos:-1,block_entities:[{id:"minecraft:chest",x:20,y:64,z:-10}],
+                structures:{References:{a:[L;1L]},starts:{}}}"#,
+        );
+        shift_terrain(&mut chunk, 2, -3, None);
+        assert_eq!(
+            chunk,
+            compound(
+                r#"{xPos:3,zPos:-4,block_entities:[{id:"minecraft:chest",x:52,y:64,z:-58}],
+                    structures:{References:{},starts:{}}}"#
+            )
+        );
+
+        let mut old = compound(
+            r#"{Level:{xPos:1,zPos:-1,TileEntities:[{id:"minecraft:chest",x:20,y:64,z:-10}],
+                TileTicks:[{i:"minecraft:water",x:20,y:64,z:-10}],
+                Entities:[{Pos:[20.5d,64d,-10.5d],Leash:{X:21,Y:64,Z:-11}}],
+                Structures:{References:{a:[L;1L]},Starts:{}}}}"#,
+        );
+        shift_terrain(&mut old, 2, -3, None);
+        assert_eq!(
+            old,
+            compound(
+                r#"{Level:{xPos:3,zPos:-4,TileEntities:[{id:"minecraft:chest",x:52,y:64,z:-58}],
+                    TileTicks:[{i:"minecraft:water",x:52,y:64,z:-58}],
+                    Entities:[{Pos:[52.5d,64d,-58.5d],Leash:{X:53,Y:64,Z:-59}}],
+                    Structures:{References:{},Starts:{}}}}"#
+            )
+        );
+
+        let mut villager = compound(
+            r#"{Pos:[20.5d,64d,-10.5d],sleeping_pos:[I;21,64,-11],HomePosX:20,HomePosZ:-10,
+                Brain:{memories:{"minecraft:home":{value:{pos:[I;21,64,-11],
+                dimension:"minecraft:overworld"}}}}}"#,
+        );
+        shift_entity(&mut villager, 1, 1, None);
+        assert_eq!(
+            villager,
+            compound(
+                r#"{Pos:[36.5d,64d,5.5d],sleeping_pos:[I;37,64,5],HomePosX:36,HomePosZ:6,
+                    Brain:{memories:{"minecraft:home":{value:{pos:[I;37,64,5],
+                    dimension:"minecraft:overworld"}}}}}"#
+            )
+        );
+
+        let mut entities = compound(
+            r#"{Position:[I;1,-1],Entities:[{Pos:[20.5d,64d,-10.5d],UUID:[I;1,2,3,4],
+                Passengers:[{Pos:[20.5d,65d,-10.5d],UUID:[I;5,6,7,8]}]}]}"#,
+        );
+        let uuids = UuidSource(RandomState::new());
+        shift_entities(&mut entities, 1, 1, Some(&uuids));
+        let Some(Value::List(list)) = entities.get("Entities") else {
+            panic!("expected entities");
+        };
+        let Value::Compound(entity) = &list[0] else {
+            panic!("expected an entity");
+        };
+        assert_eq!(
+            entity["Pos"],
+            Value::List(vec![
+                Value::Double(36.5),
+                Value::Double(64.0),
+                Value::Double(5.5)
+            ])
+        );
+        assert_ne!(
+            entity["UUID"],
+            Value::IntArray(IntArray::new(vec![1, 2, 3, 4]))
+        );
+        assert_eq!(
+            entities["Position"],
+            Value::IntArray(IntArray::new(vec![2, 0]))
+        );
+    }
+
+    /// Writes chunks, each tagged with `from`, into a region file of a world
+    fn write_world(world: &Path, kind: &str, chunks: &[(i32, i32)], from: &str) {
+        let path = world.join(kind).join("r.0.0.mca");
+        let mut reg = open_region_for_writing(&path).unwrap();
+        for (x, z) in chunks {
+            let chunk = compound(&format!(r#"{{xPos:{x},zPos:{z},from:"{from}"}}"#));
+            reg.write_chunk(*x as usize, *z as usize, &to_bytes(&chunk).unwrap())
+                .unwrap();
+        }
+        close_region(reg, &[]).unwrap();
+    }
+
+    /// Where each chunk of a region file came from, and its timestamp
+    fn read_world(world: &Path, kind: &str) -> BTreeMap<(i32, i32), (String, u32)> {
+        let path = world.join(kind).join("r.0.0.mca");
+        let header = fs::read(&path).unwrap();
+        let mut reg = Region::from_stream(File::open(&path).unwrap()).unwrap();
+        let mut chunks = BTreeMap::new();
+        for chunk in reg.iter() {
+            let chunk = chunk.unwrap();
+            let data: Compound = fastnbt::from_bytes(&chunk.data).unwrap();
+            let Some(Value::String(from)) = data.get("from") else {
+                panic!("expected a tag");
+            };
+            let offset = 4096 + (chunk.z * 32 + chunk.x) * 4;
+            let timestamp = u32::from_be_bytes(header[offset..offset + 4].try_into().unwrap());
+            chunks.insert((chunk.x as i32, chunk.z as i32), (from.clone(), timestamp));
+        }
+        chunks
+    }
+
+    #[test]
+    fn restoring_an_area() {
+        let root = std::env::temp_dir().join(format!("mcl-restore-area-{}", std::process::id()));
+        let (saved, live) = (root.join("saved"), root.join("live"));
+        write_world(&saved, "region", &[(0, 0), (2, 0), (3, 0)], "saved");
+        write_world(&saved, "entities", &[(0, 0)], "saved");
+        write_world(&live, "region", &[(0, 0), (1, 0), (2, 0), (3, 0)], "live");
+        write_world(&live, "entities", &[(0, 0), (2, 0), (3, 0)], "live");
+
+        let backup = Backup::new(&root.join("backups"), "restore-area").unwrap();
+        // chunks 0 to 2 of the first row
+        let args = RestoreAreaArgs {
+            from_world: saved,
+            world: live.clone(),
+            dimension: Dimension::Overworld,
+            from: (0, 0, 0),
+            to: (47, 0, 15),
+        };
+        restore_area(&args, &backup).unwrap();
+
+        let terrain = read_world(&live, "region");
+        let from: Vec<_> = terrain
+            .iter()
+            .map(|(c, (from, _))| (*c, from.as_str()))
+            .collect();
+        assert_eq!(
+            from,
+            [
+                ((0, 0), "saved"),
+                // missing from the backup
+                ((1, 0), "live"),
+                ((2, 0), "saved"),
+                // outside the area
+                ((3, 0), "live"),
+            ]
+        );
+        assert!(terrain[&(0, 0)].1 > 0);
+        assert_eq!(terrain[&(1, 0)].1, 0);
+
+        // the entities in chunk 2 didn't exist when the backup was made
+        let entities = read_world(&live, "entities");
+        let from: Vec<_> = entities
+            .iter()
+            .map(|(c, (from, _))| (*c, from.as_str()))
+            .collect();
+        assert_eq!(from, [((0, 0), "saved"), ((3, 0), "live")]);
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn copying_over_entities() {
+        let root = std::env::temp_dir().join(format!("mcl-copy-chunks-{}", std::process::id()));
+        let (source, dest) = (root.join("source"), root.join("dest"));
+        write_world(&source, "region", &[(0, 0)], "source");
+        write_world(&dest, "region", &[(1, 0), (2, 0)], "dest");
+        write_world(&dest, "entities", &[(1, 0), (2, 0)], "dest");
+        write_world(&dest, "poi", &[(1, 0)], "dest");
+
+        let backup = Backup::new(&root.join("backups"), "copy-chunks").unwrap();
+        copy_chunks(
+            &CopySpec {
+                source: &source,
+                source_dimension: &Dimension::Overworld,
+                world: &dest,
+                dimension: &Dimension::Overworld,
+                from: None,
+                to: None,
+                offset: (1, 0),
+            },
+            &backup,
+        )
+        .unwrap();
+
+        let from = |kind| -> Vec<_> {
+            read_world(&dest, kind)
+                .into_iter()
+                .map(|(c, (from, _))| (c, from))
+                .collect()
+        };
+        assert_eq!(
+            from("region"),
+            [((1, 0), "source".to_string()), ((2, 0), "dest".to_string())]
+        );
+        // the source has no entities or POI in the copied chunk
+        assert_eq!(from("entities"), [((2, 0), "dest".to_string())]);
+        assert_eq!(from("poi"), []);
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -0,0 +1,36 @@
+use crate::nbt::Compound;
+
+use anyhow::{Context, Result};
+use fastnbt::{from_bytes, to_bytes};
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use std::fs::{self, File};
+use std::io::{Read, Write};
+use std::path::Path;
+
+/// Reads an NBT file like `level.dat` or `playerdata/<uuid>.dat`, which are
+/// usually gzipped.
+pub(crate) fn read_dat(path: &Path) -> Result<Compound> {
+    let data = fs::read(path).with_context(|| format!("Failed to read `{}`", path.display()))?;
+    let data = if data.starts_with(&[0x1f, 0x8b]) {
+        let mut decoded = vec![];
+        GzDecoder::new(data.as_slice()).read_to_end(&mut decoded)?;
+        decoded
+    } else {
+        data
+    };
+    from_bytes(&data).with_context(|| format!("Failed to parse `{}`", path.display()))
+}
+
+/// Writes a gzipped NBT file, replacing `path` only once it's complete.
+pub(crate) fn write_dat(path: &Path, compound: &Compound) -> Result<()> {
+    let tmp = path.with_extension("dat.mcl-tmp");
+    {
+        let mut encoder = GzEncoder::new(File::create(&tmp)?, Compression::default());
+        encoder.write_all(&to_bytes(compound)?)?;
+        encoder.finish()?.sync_all()?;
+    }
+    fs::rename(&tmp, path).with_context(|| format!("Failed to write `{}`", path.display()))?;
+    Ok(())
+}
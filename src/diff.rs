@@ -0,0 +1,678 @@
+use crate::blocks::section_indices;
+use crate::cli::{Coords, DiffArgs};
+use crate::entities::{entity_block, entity_id, entity_uuid};
+use crate::items::container_items;
+use crate::nbt::{get_int, get_string, load_chunk, Compound, Section};
+use crate::prune::list_region_files;
+use crate::region::{dimension_dir, read_header, region_coords};
+use crate::{rect_intersects_bounds, within_bounds};
+
+use anyhow::{Context, Result};
+use fastanvil::Region;
+use fastnbt::{from_bytes, Value};
+use serde::Serialize;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fs::{self, File};
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::path::Path;
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+enum Change {
+    Added,
+    Removed,
+    Changed,
+}
+
+impl Change {
+    fn name(&self) -> &'static str {
+        match self {
+            Change::Added => "added",
+            Change::Removed => "removed",
+            Change::Changed => "changed",
+        }
+    }
+}
+
+#[derive(Serialize, Debug)]
+struct BlockEntityDiff {
+    pos: Coords,
+    id: String,
+    change: Change,
+    /// item counts by id in the second world minus the first
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    items: BTreeMap<String, i64>,
+}
+
+#[derive(Serialize, Debug)]
+struct EntityDiff {
+    id: String,
+    uuid: String,
+    change: Change,
+    from: Option<Coords>,
+    to: Option<Coords>,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    items: BTreeMap<String, i64>,
+}
+
+/// Everything that differs in a chunk. Blocks are counted by id: a stone
+/// block replaced by dirt counts as a stone removed and a dirt added.
+#[derive(Serialize, Debug)]
+struct ChunkDiff {
+    x: i32,
+    z: i32,
+    change: Change,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    blocks_added: BTreeMap<String, u64>,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    blocks_removed: BTreeMap<String, u64>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    block_entities: Vec<BlockEntityDiff>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    entities: Vec<EntityDiff>,
+}
+
+impl ChunkDiff {
+    fn new(x: i32, z: i32, change: Change) -> ChunkDiff {
+        ChunkDiff {
+            x,
+            z,
+            change,
+            blocks_added: BTreeMap::new(),
+            blocks_removed: BTreeMap::new(),
+            block_entities: vec![],
+            entities: vec![],
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.blocks_added.is_empty()
+            && self.blocks_removed.is_empty()
+            && self.block_entities.is_empty()
+            && self.entities.is_empty()
+    }
+}
+
+/// How many chunks fell in each category, for the summary
+#[derive(Default)]
+struct Tally {
+    unchanged: u64,
+    resaved: u64,
+    changed: u64,
+    added: u64,
+    removed: u64,
+    /// couldn't be compared, one of the versions didn't parse
+    unreadable: u64,
+}
+
+/// A chunk as stored in a region file: its timestamp and its uncompressed
+/// NBT
+struct RawChunk {
+    timestamp: u32,
+    data: Vec<u8>,
+}
+
+impl RawChunk {
+    fn hash(&self) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        self.data.hash(&mut hasher);
+        hasher.finish()
+    }
+}
+
+/// Chunks of a region file by their position in the region. A missing or
+/// empty file has no chunks.
+fn read_region(path: &Path) -> Result<HashMap<(usize, usize), RawChunk>> {
+    let mut chunks = HashMap::new();
+    if !path.is_file() || fs::metadata(path)?.len() == 0 {
+        return Ok(chunks);
+    }
+    let timestamps: HashMap<(usize, usize), u32> = read_header(&mut File::open(path)?)?
+        .into_iter()
+        .map(|loc| ((loc.x, loc.z), loc.timestamp))
+        .collect();
+    let mut reg = Region::from_stream(File::open(path)?)?;
+    for raw_chunk in reg.iter() {
+        let raw_chunk = raw_chunk?;
+        let pos = (raw_chunk.x, raw_chunk.z);
+        chunks.insert(
+            pos,
+            RawChunk {
+                timestamp: timestamps.get(&pos).copied().unwrap_or(0),
+                data: raw_chunk.data,
+            },
+        );
+    }
+    Ok(chunks)
+}
+
+/// Names of the region files in either directory
+fn region_file_names(a: &Path, b: &Path) -> Result<BTreeSet<String>> {
+    let mut names = BTreeSet::new();
+    for dir in [a, b] {
+        if !dir.is_dir() {
+            continue;
+        }
+        for path in list_region_files(&dir.to_path_buf())? {
+            if let Some(name) = path.file_name() {
+                names.insert(name.to_string_lossy().into_owned());
+            }
+        }
+    }
+    Ok(names)
+}
+
+/// Calls `f` with the chunks that differ between the region files of the
+/// two directories, `None` standing for a missing chunk. Chunks with the
+/// same content are tallied as unchanged or, if only their timestamp
+/// differs, as saved again; `f` tallies the rest.
+fn for_each_changed_chunk(
+    dir_a: &Path,
+    dir_b: &Path,
+    args: &DiffArgs,
+    mut f: impl FnMut(&mut Tally, i32, i32, Option<&RawChunk>, Option<&RawChunk>) -> Result<()>,
+) -> Result<Tally> {
+    let mut tally = Tally::default();
+    let from = args.from.as_ref();
+    let to = args.to.as_ref();
+    for name in region_file_names(dir_a, dir_b)? {
+        let (path_a, path_b) = (dir_a.join(&name), dir_b.join(&name));
+        let (reg_x, reg_z) = region_coords(&path_b)?;
+
+        let rf = (reg_x * 512, reg_z * 512);
+        let rt = (reg_x * 512 + 511, reg_z * 512 + 511);
+        if !rect_intersects_bounds(rf, rt, from, to) {
+            log::debug!("region {reg_x} {reg_z} doesn't intersect bounds, skipping");
+            continue;
+        }
+
+        log::debug!("comparing {} and {}", path_a.display(), path_b.display());
+        let chunks_a =
+            read_region(&path_a).with_context(|| format!("reading {}", path_a.display()))?;
+        let chunks_b =
+            read_region(&path_b).with_context(|| format!("reading {}", path_b.display()))?;
+        let slots: BTreeSet<&(usize, usize)> = chunks_a.keys().chain(chunks_b.keys()).collect();
+        for &(rx, rz) in slots {
+            let x = reg_x * 32 + rx as i32;
+            let z = reg_z * 32 + rz as i32;
+            let cf = (x * 16, z * 16);
+            let ct = (x * 16 + 15, z * 16 + 15);
+            if !rect_intersects_bounds(cf, ct, from, to) {
+                continue;
+            }
+
+            let a = chunks_a.get(&(rx, rz));
+            let b = chunks_b.get(&(rx, rz));
+            if let (Some(a), Some(b)) = (a, b) {
+                if a.hash() == b.hash() {
+                    if a.timestamp == b.timestamp {
+                        tally.unchanged += 1;
+                    } else {
+                        tally.resaved += 1;
+                    }
+                    continue;
+                }
+            }
+            f(&mut tally, x, z, a, b)?;
+        }
+    }
+    Ok(tally)
+}
+
+/// Block ids of a section in YZX order, `None` if the section has no
+/// block states
+fn section_blocks(section: &Section) -> Option<Vec<&str>> {
+    let block_states = section.block_states.as_ref()?;
+    let palette = block_states.palette();
+    Some(
+        section_indices(block_states)?
+            .map(|i| palette[i].name())
+            .collect(),
+    )
+}
+
+/// Block ids of the sections of a chunk, by section Y
+fn chunk_blocks(sections: &[Section]) -> BTreeMap<i8, Vec<&str>> {
+    sections
+        .iter()
+        .filter_map(|s| Some((s.y, section_blocks(s)?)))
+        .collect()
+}
+
+/// Counts the blocks that differ between two versions of a chunk
+fn diff_blocks(
+    x: i32,
+    z: i32,
+    a: &[u8],
+    b: &[u8],
+    args: &DiffArgs,
+    diff: &mut ChunkDiff,
+) -> Result<()> {
+    let (chunk_a, chunk_b) = (load_chunk(a)?, load_chunk(b)?);
+    let sections_a = chunk_blocks(chunk_a.sections());
+    let sections_b = chunk_blocks(chunk_b.sections());
+    let all_air = vec!["minecraft:air"; 4096];
+
+    let ys: BTreeSet<&i8> = sections_a.keys().chain(sections_b.keys()).collect();
+    for y in ys {
+        let blocks_a = sections_a.get(y).unwrap_or(&all_air);
+        let blocks_b = sections_b.get(y).unwrap_or(&all_air);
+        for (i, (block_a, block_b)) in blocks_a.iter().zip(blocks_b.iter()).enumerate() {
+            if block_a == block_b {
+                continue;
+            }
+            let bx = x * 16 + (i & 0x000F) as i32;
+            let by = *y as i32 * 16 + ((i & 0x0F00) >> 8) as i32;
+            let bz = z * 16 + ((i & 0x00F0) >> 4) as i32;
+            if !within_bounds(&(bx, by, bz), args.from.as_ref(), args.to.as_ref()) {
+                continue;
+            }
+            *diff.blocks_removed.entry(block_a.to_string()).or_default() += 1;
+            *diff.blocks_added.entry(block_b.to_string()).or_default() += 1;
+        }
+    }
+    Ok(())
+}
+
+/// Item counts by id in the second container minus the first, without the
+/// ids whose count didn't change
+fn item_deltas(a: Option<&Compound>, b: Option<&Compound>) -> BTreeMap<String, i64> {
+    let mut deltas: BTreeMap<String, i64> = BTreeMap::new();
+    for (container, sign) in [(a, -1), (b, 1)] {
+        for item in container.map(container_items).unwrap_or_default() {
+            *deltas.entry(item.id).or_default() += sign * item.count;
+        }
+    }
+    deltas.retain(|_, delta| *delta != 0);
+    deltas
+}
+
+/// Block entities of a chunk within the bounds, by position
+fn block_entities<'a>(chunk: &'a Compound, args: &DiffArgs) -> BTreeMap<Coords, &'a Compound> {
+    let list = chunk
+        .get("block_entities")
+        .or_else(|| match chunk.get("Level") {
+            Some(Value::Compound(level)) => level.get("TileEntities"),
+            _ => None,
+        });
+    let Some(Value::List(list)) = list else {
+        return BTreeMap::new();
+    };
+    list.iter()
+        .filter_map(|entity| {
+            let Value::Compound(entity) = entity else {
+                return None;
+            };
+            let pos = (
+                get_int(entity, "x")?,
+                get_int(entity, "y")?,
+                get_int(entity, "z")?,
+            );
+            within_bounds(&pos, args.from.as_ref(), args.to.as_ref()).then_some((pos, entity))
+        })
+        .collect()
+}
+
+fn diff_block_entities(a: &[u8], b: &[u8], args: &DiffArgs, diff: &mut ChunkDiff) -> Result<()> {
+    let chunk_a: Compound = from_bytes(a)?;
+    let chunk_b: Compound = from_bytes(b)?;
+    let entities_a = block_entities(&chunk_a, args);
+    let entities_b = block_entities(&chunk_b, args);
+
+    let positions: BTreeSet<&Coords> = entities_a.keys().chain(entities_b.keys()).collect();
+    for pos in positions {
+        let a = entities_a.get(pos).copied();
+        let b = entities_b.get(pos).copied();
+        let change = match (a, b) {
+            (Some(a), Some(b)) if a == b => continue,
+            (Some(_), Some(_)) => Change::Changed,
+            (None, _) => Change::Added,
+            (_, None) => Change::Removed,
+        };
+        let id = b
+            .or(a)
+            .and_then(|e| get_string(e, "id"))
+            .unwrap_or_default();
+        diff.block_entities.push(BlockEntityDiff {
+            pos: *pos,
+            id,
+            change,
+            items: item_deltas(a, b),
+        });
+    }
+    Ok(())
+}
+
+/// Entities of an entities chunk with an UUID, by UUID
+fn chunk_entities(data: &[u8]) -> Result<Vec<(String, Compound)>> {
+    let mut chunk: Compound = from_bytes(data)?;
+    let Some(Value::List(entities)) = chunk.remove("Entities") else {
+        return Ok(vec![]);
+    };
+    Ok(entities
+        .into_iter()
+        .filter_map(|entity| match entity {
+            Value::Compound(entity) => Some((entity_uuid(&entity)?, entity)),
+            _ => None,
+        })
+        .collect())
+}
+
+/// Compares the entities found in the chunks that changed. Entities are
+/// matched by UUID across chunks, so one that walked into the next chunk
+/// shows up as moved. Other changes, like health or age, are left out:
+/// they happen all the time to every loaded entity.
+fn diff_entities(
+    entities_a: HashMap<String, ((i32, i32), Compound)>,
+    mut entities_b: HashMap<String, ((i32, i32), Compound)>,
+    args: &DiffArgs,
+    diffs: &mut BTreeMap<(i32, i32), ChunkDiff>,
+) {
+    let from = args.from.as_ref();
+    let to = args.to.as_ref();
+    let mut found = vec![];
+    for (uuid, (chunk_a, a)) in entities_a {
+        let b = entities_b.remove(&uuid);
+        let (chunk, change) = match &b {
+            Some((chunk_b, _)) => (*chunk_b, Change::Changed),
+            None => (chunk_a, Change::Removed),
+        };
+        found.push((chunk, uuid, Some(a), b.map(|(_, b)| b), change));
+    }
+    for (uuid, (chunk_b, b)) in entities_b {
+        found.push((chunk_b, uuid, None, Some(b), Change::Added));
+    }
+
+    for (chunk, uuid, a, b, change) in found {
+        let pos_a = a.as_ref().and_then(entity_block);
+        let pos_b = b.as_ref().and_then(entity_block);
+        let in_bounds = [pos_a, pos_b]
+            .iter()
+            .flatten()
+            .any(|pos| within_bounds(pos, from, to));
+        if !in_bounds {
+            continue;
+        }
+        let items = item_deltas(a.as_ref(), b.as_ref());
+        if change == Change::Changed && pos_a == pos_b && items.is_empty() {
+            continue;
+        }
+        let id = b
+            .as_ref()
+            .or(a.as_ref())
+            .and_then(entity_id)
+            .unwrap_or_default()
+            .to_string();
+        diffs
+            .entry(chunk)
+            .or_insert_with(|| ChunkDiff::new(chunk.0, chunk.1, Change::Changed))
+            .entities
+            .push(EntityDiff {
+                id,
+                uuid,
+                change,
+                from: pos_a,
+                to: pos_b,
+                items,
+            });
+    }
+}
+
+fn format_coords(pos: Option<Coords>) -> String {
+    match pos {
+        Some((x, y, z)) => format!("{x} {y} {z}"),
+        None => "?".to_string(),
+    }
+}
+
+fn format_items(items: &BTreeMap<String, i64>) -> String {
+    items
+        .iter()
+        .map(|(id, delta)| format!("{delta:+} {id}"))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+fn print_diff(diff: &ChunkDiff) {
+    println!("chunk {} {}: {}", diff.x, diff.z, diff.change.name());
+    if !diff.blocks_added.is_empty() {
+        let added = diff.blocks_added.iter().map(|(id, n)| format!("+{n} {id}"));
+        let removed = diff
+            .blocks_removed
+            .iter()
+            .map(|(id, n)| format!("-{n} {id}"));
+        println!(
+            "  blocks: {}",
+            added.chain(removed).collect::<Vec<_>>().join(", ")
+        );
+    }
+    for be in diff.block_entities.iter() {
+        let (x, y, z) = be.pos;
+        print!(
+            "  block entity {} at {x} {y} {z}: {}",
+            be.id,
+            be.change.name()
+        );
+        if !be.items.is_empty() {
+            print!(", items {}", format_items(&be.items));
+        }
+        println!();
+    }
+    for e in diff.entities.iter() {
+        print!("  entity {} {}: ", e.id, e.uuid);
+        match e.change {
+            Change::Added => print!("added at {}", format_coords(e.to)),
+            Change::Removed => print!("removed from {}", format_coords(e.from)),
+            Change::Changed if e.from != e.to => print!(
+                "moved from {} to {}",
+                format_coords(e.from),
+                format_coords(e.to)
+            ),
+            Change::Changed => print!("changed at {}", format_coords(e.to)),
+        }
+        if !e.items.is_empty() {
+            print!(", items {}", format_items(&e.items));
+        }
+        println!();
+    }
+}
+
+/// Compares the terrain chunks of two region directories, adding the ones
+/// that changed to `diffs`
+fn diff_terrain(
+    dir_a: &Path,
+    dir_b: &Path,
+    args: &DiffArgs,
+    diffs: &mut BTreeMap<(i32, i32), ChunkDiff>,
+) -> Result<Tally> {
+    for_each_changed_chunk(dir_a, dir_b, args, |tally, x, z, a, b| {
+        let (a, b) = match (a, b) {
+            (Some(a), Some(b)) => (a, b),
+            (None, _) => {
+                tally.added += 1;
+                diffs.insert((x, z), ChunkDiff::new(x, z, Change::Added));
+                return Ok(());
+            }
+            (_, None) => {
+                tally.removed += 1;
+                diffs.insert((x, z), ChunkDiff::new(x, z, Change::Removed));
+                return Ok(());
+            }
+        };
+        let mut diff = ChunkDiff::new(x, z, Change::Changed);
+        let blocks = diff_blocks(x, z, &a.data, &b.data, args, &mut diff);
+        let block_entities = diff_block_entities(&a.data, &b.data, args, &mut diff);
+        let mut readable = true;
+        for (what, compared) in [("blocks", blocks), ("block entities", block_entities)] {
+            if let Err(e) = compared {
+                log::warn!("can't compare the {what} of chunk {x} {z}: {e}");
+                readable = false;
+            }
+        }
+        if !readable {
+            // whatever could be compared is still shown
+            tally.unreadable += 1;
+            if !diff.is_empty() {
+                diffs.insert((x, z), diff);
+            }
+        } else if diff.is_empty() {
+            // LastUpdate, InhabitedTime, lighting and the like
+            tally.resaved += 1;
+        } else {
+            tally.changed += 1;
+            diffs.insert((x, z), diff);
+        }
+        Ok(())
+    })
+}
+
+pub(crate) fn diff(args: &DiffArgs) -> Result<()> {
+    let dim_a = dimension_dir(&args.world_a, &args.dimension);
+    let dim_b = dimension_dir(&args.world_b, &args.dimension);
+    let mut diffs: BTreeMap<(i32, i32), ChunkDiff> = BTreeMap::new();
+
+    let tally = diff_terrain(
+        &dim_a.join("region"),
+        &dim_b.join("region"),
+        args,
+        &mut diffs,
+    )?;
+
+    // entities are kept in their own chunks since 1.17, they're compared
+    // once all the changed ones are read; the tally is only about terrain
+    let mut entities_a = HashMap::new();
+    let mut entities_b = HashMap::new();
+    for_each_changed_chunk(
+        &dim_a.join("entities"),
+        &dim_b.join("entities"),
+        args,
+        |_, x, z, a, b| {
+            for (raw, entities) in [(a, &mut entities_a), (b, &mut entities_b)] {
+                let Some(raw) = raw else {
+                    continue;
+                };
+                match chunk_entities(&raw.data) {
+                    Ok(found) => {
+                        entities.extend(found.into_iter().map(|(uuid, e)| (uuid, ((x, z), e))))
+                    }
+                    Err(e) => log::warn!("can't read the entities of chunk {x} {z}: {e}"),
+                }
+            }
+            Ok(())
+        },
+    )?;
+    diff_entities(entities_a, entities_b, args, &mut diffs);
+
+    for diff in diffs.values() {
+        if args.json {
+            println!("{}", serde_json::to_string(diff)?);
+        } else {
+            print_diff(diff);
+        }
+    }
+    log::info!(
+        "chunks: {} unchanged, {} saved again without changes, {} changed, {} added, {} removed, {} unreadable",
+        tally.unchanged,
+        tally.resaved,
+        tally.changed,
+        tally.added,
+        tally.removed,
+        tally.unreadable
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cli::Dimension;
+    use crate::region::{close_region, open_region_for_writing};
+    use crate::snbt;
+
+    fn chunk(inhabited: i64, block: &str) -> Vec<u8> {
+        let text = format!(
+            r#"{{DataVersion:3465,InhabitedTime:{inhabited}L,
+                sections:[{{Y:0b,block_states:{{palette:[{{Name:"minecraft:{block}"}}]}}}}]}}"#
+        );
+        fastnbt::to_bytes(&snbt::parse(&text).unwrap()).unwrap()
+    }
+
+    /// Writes a region file, stamping the chunks in `stamped` with the
+    /// current time and leaving the rest at 0
+    fn write_region(dir: &Path, chunks: &[(usize, &[u8])], stamped: &[usize]) {
+        let mut reg = open_region_for_writing(&dir.join("r.0.0.mca")).unwrap();
+        for (x, data) in chunks {
+            reg.write_chunk(*x, 0, data).unwrap();
+        }
+        let stamped: Vec<_> = stamped.iter().map(|x| (*x, 0)).collect();
+        close_region(reg, &stamped).unwrap();
+    }
+
+    #[test]
+    fn terrain_tally() {
+        let root = std::env::temp_dir().join(format!("mcl-diff-{}", std::process::id()));
+        let (dir_a, dir_b) = (root.join("a"), root.join("b"));
+        // compounds don't keep their order, the same bytes must be reused
+        let stone = chunk(0, "stone");
+        let no_sections =
+            fastnbt::to_bytes(&snbt::parse("{DataVersion:3465,InhabitedTime:0L}").unwrap())
+                .unwrap();
+        write_region(
+            &dir_a,
+            &[
+                (0, &stone),
+                (1, &stone),
+                (2, &stone),
+                (3, &stone),
+                (5, &stone),
+                (6, &stone),
+            ],
+            &[],
+        );
+        write_region(
+            &dir_b,
+            &[
+                // unchanged
+                (0, &stone),
+                // saved again, as is and with a new InhabitedTime
+                (1, &stone),
+                (2, &chunk(100, "stone")),
+                // changed
+                (3, &chunk(0, "dirt")),
+                // added
+                (4, &stone),
+                // no sections
+                (6, &no_sections),
+            ],
+            &[1, 2, 3, 4, 6],
+        );
+
+        let args = DiffArgs {
+            world_a: dir_a.clone(),
+            world_b: dir_b.clone(),
+            dimension: Dimension::Overworld,
+            from: None,
+            to: None,
+            json: false,
+        };
+        let mut diffs = BTreeMap::new();
+        let tally = diff_terrain(&dir_a, &dir_b, &args, &mut diffs).unwrap();
+        fs::remove_dir_all(&root).unwrap();
+
+        assert_eq!(
+            (
+                tally.unchanged,
+                tally.resaved,
+                tally.changed,
+                tally.added,
+                tally.removed,
+                tally.unreadable
+            ),
+            (1, 2, 1, 1, 1, 1)
+        );
+        let changes: Vec<_> = diffs.values().map(|d| (d.x, d.change.name())).collect();
+        assert_eq!(changes, [(3, "changed"), (4, "added"), (5, "removed")]);
+        assert_eq!(diffs[&(3, 0)].blocks_added["minecraft:dirt"], 4096);
+        assert_eq!(diffs[&(3, 0)].blocks_removed["minecraft:stone"], 4096);
+    }
+}
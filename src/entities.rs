@@ -2,53 +2,175 @@ use super::rect_intersects_bounds;
 use super::within_bounds;
 use crate::cli::Coords;
 use crate::cli::Dimension;
-use crate::nbt::load_chunk;
-use crate::nbt::BlockEntity;
+use crate::cli::EntitiesArgs;
+use crate::cli::{CensusArgs, OutputFormat};
+use crate::nbt::Compound;
 use crate::prune::list_region_files;
-use anyhow::{Context, Result};
+use crate::region::{dimension_dir, region_coords};
+use crate::selector::NameMatcher;
+use anyhow::Result;
 use fastanvil::Region;
 use fastnbt::from_bytes;
-use fastnbt::from_value;
 use fastnbt::Value;
-use std::collections::HashMap;
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
 use std::fs::File;
 use std::path::Path;
 
-pub(crate) fn entities(
+/// Position of an entity, from its `Pos` list
+pub(crate) fn entity_pos(entity: &Compound) -> Option<(f64, f64, f64)> {
+    let Some(Value::List(pos)) = entity.get("Pos") else {
+        return None;
+    };
+    match pos.as_slice() {
+        [Value::Double(x), Value::Double(y), Value::Double(z)] => Some((*x, *y, *z)),
+        _ => None,
+    }
+}
+
+/// Block containing the entity
+pub(crate) fn entity_block(entity: &Compound) -> Option<Coords> {
+    let (x, y, z) = entity_pos(entity)?;
+    Some((x.floor() as i32, y.floor() as i32, z.floor() as i32))
+}
+
+pub(crate) fn entity_id(entity: &Compound) -> Option<&str> {
+    match entity.get("id") {
+        Some(Value::String(id)) => Some(id.as_str()),
+        _ => None,
+    }
+}
+
+/// Formats an UUID stored as four ints, like 069a79f4-44e9-4726-a5be-fca90e38aaf5
+pub(crate) fn format_uuid(uuid: &[i32]) -> Option<String> {
+    let [a, b, c, d] = uuid else {
+        return None;
+    };
+    let n = ((*a as u32 as u128) << 96)
+        | ((*b as u32 as u128) << 64)
+        | ((*c as u32 as u128) << 32)
+        | (*d as u32 as u128);
+    let hex = format!("{n:032x}");
+    Some(format!(
+        "{}-{}-{}-{}-{}",
+        &hex[0..8],
+        &hex[8..12],
+        &hex[12..16],
+        &hex[16..20],
+        &hex[20..32]
+    ))
+}
+
+pub(crate) fn entity_uuid(entity: &Compound) -> Option<String> {
+    match entity.get("UUID") {
+        Some(Value::IntArray(uuid)) => format_uuid(uuid),
+        _ => None,
+    }
+}
+
+/// Plain text of a text component, either in JSON (before 1.21.5) or in NBT
+fn component_text(component: &Value) -> String {
+    match component {
+        Value::String(s) => match serde_json::from_str::<serde_json::Value>(s) {
+            Ok(json) => json_text(&json),
+            Err(_) => s.clone(),
+        },
+        Value::Compound(c) => {
+            let mut text = c.get("text").map(component_text).unwrap_or_default();
+            if let Some(Value::List(extra)) = c.get("extra") {
+                text.extend(extra.iter().map(component_text));
+            }
+            text
+        }
+        Value::List(parts) => parts.iter().map(component_text).collect(),
+        _ => String::new(),
+    }
+}
+
+fn json_text(component: &serde_json::Value) -> String {
+    match component {
+        serde_json::Value::String(s) => s.clone(),
+        serde_json::Value::Object(o) => {
+            let mut text = o.get("text").map(json_text).unwrap_or_default();
+            if let Some(serde_json::Value::Array(extra)) = o.get("extra") {
+                text.extend(extra.iter().map(json_text));
+            }
+            text
+        }
+        serde_json::Value::Array(parts) => parts.iter().map(json_text).collect(),
+        _ => String::new(),
+    }
+}
+
+pub(crate) fn entity_name(entity: &Compound) -> Option<String> {
+    entity.get("CustomName").map(component_text)
+}
+
+/// Selects entities by id, custom name and UUID
+#[derive(Debug, Default)]
+pub(crate) struct EntityFilter {
+    pub(crate) id: Option<NameMatcher>,
+    pub(crate) name: Option<NameMatcher>,
+    pub(crate) uuid: Option<String>,
+}
+
+impl EntityFilter {
+    pub(crate) fn from_args(args: &EntitiesArgs) -> Result<EntityFilter> {
+        Ok(EntityFilter {
+            id: args.id.as_deref().map(NameMatcher::parse).transpose()?,
+            name: args
+                .name
+                .as_deref()
+                .map(NameMatcher::parse_text)
+                .transpose()?,
+            uuid: args
+                .uuid
+                .as_ref()
+                .map(|u| u.to_lowercase().replace('-', "")),
+        })
+    }
+
+    pub(crate) fn matches(&self, entity: &Compound) -> bool {
+        self.id
+            .as_ref()
+            .is_none_or(|id| entity_id(entity).is_some_and(|e| id.matches(e)))
+            && self
+                .name
+                .as_ref()
+                .is_none_or(|name| entity_name(entity).is_some_and(|n| name.matches(&n)))
+            && self
+                .uuid
+                .as_ref()
+                .is_none_or(|uuid| entity_uuid(entity).is_some_and(|u| u.replace('-', "") == *uuid))
+    }
+}
+
+/// Calls `f` with the chunk coordinates and the compound of every entity
+/// within the bounds
+pub(crate) fn for_each_entity(
     world: &Path,
-    dimension: Dimension,
-    from: Option<Coords>,
-    to: Option<Coords>,
-    json: bool,
+    dimension: &Dimension,
+    from: Option<&Coords>,
+    to: Option<&Coords>,
+    mut f: impl FnMut((i32, i32), &Compound) -> Result<()>,
 ) -> Result<()> {
-    let entities_dir = match dimension {
-        Dimension::Overworld => world.join("entities"),
-        Dimension::Nether => world.join("DIM-1/entities"),
-        Dimension::End => world.join("DIM1/entities"),
-    };
+    let entities_dir = dimension_dir(world, dimension).join("entities");
     let region_files = list_region_files(&entities_dir)?;
     for reg_file in region_files {
-        let file = File::options().read(true).write(false).open(&reg_file)?;
-
-        let stem = reg_file.file_stem().context("reading file stem")?;
-        let stem = stem.to_string_lossy();
-
-        let mut parts = stem.split('.').skip(1);
-        let reg_x: i32 = parts.next().context("parsing filename")?.parse()?;
-        let reg_z: i32 = parts.next().context("parsing filename")?.parse()?;
+        let (reg_x, reg_z) = region_coords(&reg_file)?;
 
         let rf = (reg_x * 512, reg_z * 512);
         let rt = (reg_x * 512 + 511, reg_z * 512 + 511);
-        if !rect_intersects_bounds(rf, rt, from.as_ref(), to.as_ref()) {
+        if !rect_intersects_bounds(rf, rt, from, to) {
             log::debug!("region {reg_x} {reg_z} doesn't intersect bounds, skipping");
             continue;
         }
 
         log::debug!("reading region {}", reg_file.display());
+        let file = File::options().read(true).write(false).open(&reg_file)?;
         let mut reg = match Region::from_stream(file) {
             Ok(reg) => reg,
             Err(e) => {
-                // if reading the chunk fails, we count 0 chunks pruned and continue
                 log::debug!("error reading region {}: {}", reg_file.display(), e);
                 continue;
             }
@@ -61,40 +183,219 @@ pub(crate) fn entities(
 
             let cf = (x * 16, z * 16);
             let ct = (x * 16 + 15, z * 16 + 15);
-            if !rect_intersects_bounds(cf, ct, from.as_ref(), to.as_ref()) {
+            if !rect_intersects_bounds(cf, ct, from, to) {
                 log::debug!("chunk {x} {z} doesn't intersect bounds, skipping");
                 continue;
             }
 
-            let compound: HashMap<String, Value> = from_bytes(raw_chunk.data.as_slice())?;
-            if json {
-                println!("{}", serde_json::to_string(&compound)?);
+            let compound: Compound = match from_bytes(raw_chunk.data.as_slice()) {
+                Ok(c) => c,
+                Err(e) => {
+                    log::debug!("error reading chunk {x} {z}: {}", e);
+                    continue;
+                }
+            };
+            let Some(Value::List(entities)) = compound.get("Entities") else {
+                continue;
+            };
+            for entity in entities {
+                let Value::Compound(entity) = entity else {
+                    continue;
+                };
+                let Some(pos) = entity_block(entity) else {
+                    continue;
+                };
+                if !within_bounds(&pos, from, to) {
+                    continue;
+                }
+                f((x, z), entity)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+pub(crate) fn entities(args: &EntitiesArgs) -> Result<()> {
+    let filter = EntityFilter::from_args(args)?;
+    for_each_entity(
+        &args.world,
+        &args.dimension,
+        args.from.as_ref(),
+        args.to.as_ref(),
+        |_, entity| {
+            if !filter.matches(entity) {
+                return Ok(());
+            }
+            if args.json {
+                println!("{}", serde_json::to_string(entity)?);
             } else {
-                println!("{:#?}", compound);
-            }
-
-            // let chunk = match load_chunk(raw_chunk.data.as_slice()) {
-            //     Ok(c) => c,
-            //     Err(e) => {
-            //         // if reading the chunk fails, we count 0 chunks pruned and continue
-            //         log::debug!("error reading chunk {x} {z}: {}", e);
-            //         continue;
-            //     }
-            // };
-            // for entity in chunk.block_entities() {
-            //     let pos: BlockEntity = from_value(entity)?;
-            //     let pos = (pos.x, pos.y, pos.z);
-            //     if !within_bounds(&pos, from.as_ref(), to.as_ref()) {
-            //         log::debug!("entity {reg_x} {reg_z} doesn't intersect bounds, skipping");
-            //         continue;
-            //     }
-            //     if json {
-            //         println!("{}", serde_json::to_string(&entity)?);
-            //     } else {
-            //         println!("{:?}", entity);
-            //     }
-            // }
+                println!("{:?}", entity);
+            }
+            Ok(())
+        },
+    )
+}
+
+#[derive(Serialize, Debug)]
+struct ChunkCensus {
+    x: i32,
+    z: i32,
+    count: u64,
+    ids: BTreeMap<String, u64>,
+    above_threshold: bool,
+}
+
+#[derive(Serialize, Debug)]
+struct RegionCensus {
+    x: i32,
+    z: i32,
+    count: u64,
+}
+
+#[derive(Serialize, Debug)]
+struct Census {
+    total: u64,
+    ids: BTreeMap<String, u64>,
+    regions: Vec<RegionCensus>,
+    chunks: Vec<ChunkCensus>,
+}
+
+/// Counts entities per id, per chunk and per region, with the densest
+/// chunks and regions first
+pub(crate) fn census(args: &CensusArgs) -> Result<()> {
+    let filter = EntityFilter {
+        id: args.id.as_deref().map(NameMatcher::parse).transpose()?,
+        ..Default::default()
+    };
+
+    let mut ids: BTreeMap<String, u64> = BTreeMap::new();
+    let mut chunks: HashMap<(i32, i32), BTreeMap<String, u64>> = HashMap::new();
+    for_each_entity(
+        &args.world,
+        &args.dimension,
+        args.from.as_ref(),
+        args.to.as_ref(),
+        |chunk, entity| {
+            if !filter.matches(entity) {
+                return Ok(());
+            }
+            let id = entity_id(entity).unwrap_or("unknown").to_string();
+            *ids.entry(id.clone()).or_default() += 1;
+            *chunks.entry(chunk).or_default().entry(id).or_default() += 1;
+            Ok(())
+        },
+    )?;
+
+    let mut regions: HashMap<(i32, i32), u64> = HashMap::new();
+    let mut chunks: Vec<ChunkCensus> = chunks
+        .into_iter()
+        .map(|((x, z), ids)| {
+            let count: u64 = ids.values().sum();
+            *regions.entry((x >> 5, z >> 5)).or_default() += count;
+            ChunkCensus {
+                x,
+                z,
+                count,
+                ids,
+                above_threshold: args.threshold.is_some_and(|t| count > t),
+            }
+        })
+        .collect();
+    chunks.sort_by(|a, b| b.count.cmp(&a.count).then((a.x, a.z).cmp(&(b.x, b.z))));
+
+    let mut regions: Vec<RegionCensus> = regions
+        .into_iter()
+        .map(|((x, z), count)| RegionCensus { x, z, count })
+        .collect();
+    regions.sort_by(|a, b| b.count.cmp(&a.count).then((a.x, a.z).cmp(&(b.x, b.z))));
+
+    let flagged = chunks.iter().filter(|c| c.above_threshold).count();
+    if args.top > 0 {
+        // flagged chunks are always listed
+        let top = args.top.max(flagged);
+        chunks.truncate(top);
+        regions.truncate(args.top);
+    }
+
+    let census = Census {
+        total: ids.values().sum(),
+        ids,
+        regions,
+        chunks,
+    };
+
+    match args.format {
+        OutputFormat::Json => println!("{}", serde_json::to_string(&census)?),
+        OutputFormat::Csv => {
+            println!("kind,id,x,z,count,above_threshold");
+            for (id, count) in census.ids.iter() {
+                println!("id,{id},,,{count},");
+            }
+            for r in census.regions.iter() {
+                println!("region,,{},{},{},", r.x, r.z, r.count);
+            }
+            for c in census.chunks.iter() {
+                for (id, count) in c.ids.iter() {
+                    println!("chunk,{id},{},{},{count},{}", c.x, c.z, c.above_threshold);
+                }
+            }
+        }
+        OutputFormat::Table => {
+            let width = census.ids.keys().map(|k| k.len()).max().unwrap_or(2).max(2);
+            println!("{:width$} {:>10}", "id", "count");
+            let mut by_count: Vec<_> = census.ids.iter().collect();
+            by_count.sort_by(|a, b| b.1.cmp(a.1));
+            for (id, count) in by_count {
+                println!("{id:width$} {count:>10}");
+            }
+            println!("{:width$} {:>10}", "total", census.total);
+
+            println!();
+            println!("{:16} {:>10}", "region", "count");
+            for r in census.regions.iter() {
+                println!("{:16} {:>10}", format!("r.{}.{}.mca", r.x, r.z), r.count);
+            }
+
+            println!();
+            println!("{:16} {:>10}  ids", "chunk", "count");
+            for c in census.chunks.iter() {
+                let mut ids: Vec<_> = c.ids.iter().collect();
+                ids.sort_by(|a, b| b.1.cmp(a.1));
+                let ids: Vec<String> = ids.iter().map(|(id, n)| format!("{id} {n}")).collect();
+                println!(
+                    "{:16} {:>10}{} {}",
+                    format!("{},{}", c.x, c.z),
+                    c.count,
+                    if c.above_threshold { "!" } else { " " },
+                    ids.join(", ")
+                );
+            }
+            if let Some(threshold) = args.threshold {
+                println!();
+                println!("{flagged} chunks with more than {threshold} entities");
+            }
         }
     }
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn uuid() {
+        let uuid = [110787060, 1156138790, -1514210135, 238594805];
+        assert_eq!(
+            format_uuid(&uuid).unwrap(),
+            "069a79f4-44e9-4726-a5be-fca90e38aaf5"
+        );
+    }
+
+    #[test]
+    fn custom_name() {
+        let json = Value::String(r#"{"text":"Bob","extra":[{"text":" the villager"}]}"#.into());
+        assert_eq!(component_text(&json), "Bob the villager");
+        assert_eq!(component_text(&Value::String("\"Bob\"".into())), "Bob");
+    }
+}
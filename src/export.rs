@@ -0,0 +1,433 @@
+use crate::backup::Backup;
+use crate::cli::{ExportArgs, ExportKind, ImportArgs, NbtFormat};
+use crate::dat::read_dat;
+use crate::entities::for_each_entity;
+use crate::for_each_block_entity;
+use crate::nbt::{get_int, Compound};
+use crate::prune::list_region_files;
+use crate::region::{close_region, dimension_dir, for_each_chunk, open_region_for_writing};
+use crate::snbt;
+
+use anyhow::{bail, Context, Result};
+use fastanvil::Region;
+use fastnbt::{from_bytes, to_bytes, IntArray, Value};
+use serde_json::json;
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
+
+/// Converts a value to JSON that keeps its tag type, by wrapping it in an
+/// object keyed by type: `{"byte":1}`, `{"list":[{"int":2}]}`.
+pub(crate) fn to_typed_json(value: &Value) -> serde_json::Value {
+    match value {
+        Value::Byte(n) => json!({ "byte": n }),
+        Value::Short(n) => json!({ "short": n }),
+        Value::Int(n) => json!({ "int": n }),
+        Value::Long(n) => json!({ "long": n }),
+        Value::Float(n) => json!({ "float": n }),
+        Value::Double(n) => json!({ "double": n }),
+        Value::String(s) => json!({ "string": s }),
+        Value::ByteArray(a) => json!({ "byte_array": a.to_vec() }),
+        Value::IntArray(a) => json!({ "int_array": a.to_vec() }),
+        Value::LongArray(a) => json!({ "long_array": a.to_vec() }),
+        Value::List(items) => {
+            json!({ "list": items.iter().map(to_typed_json).collect::<Vec<_>>() })
+        }
+        Value::Compound(c) => typed_compound(c),
+    }
+}
+
+fn typed_compound(c: &Compound) -> serde_json::Value {
+    let map: serde_json::Map<String, serde_json::Value> = c
+        .iter()
+        .map(|(k, v)| (k.clone(), to_typed_json(v)))
+        .collect();
+    json!({ "compound": map })
+}
+
+/// Reverses [`to_typed_json`]
+pub(crate) fn from_typed_json(json: &serde_json::Value) -> Result<Value> {
+    let Some((tag, value)) = json
+        .as_object()
+        .filter(|o| o.len() == 1)
+        .and_then(|o| o.iter().next())
+    else {
+        bail!("expected an object with a single tag type, found {json}");
+    };
+    let value = value.clone();
+    Ok(match tag.as_str() {
+        "byte" => Value::Byte(serde_json::from_value(value)?),
+        "short" => Value::Short(serde_json::from_value(value)?),
+        "int" => Value::Int(serde_json::from_value(value)?),
+        "long" => Value::Long(serde_json::from_value(value)?),
+        "float" => Value::Float(serde_json::from_value(value)?),
+        "double" => Value::Double(serde_json::from_value(value)?),
+        "string" => Value::String(serde_json::from_value(value)?),
+        "byte_array" => Value::ByteArray(fastnbt::ByteArray::new(serde_json::from_value(value)?)),
+        "int_array" => Value::IntArray(fastnbt::IntArray::new(serde_json::from_value(value)?)),
+        "long_array" => Value::LongArray(fastnbt::LongArray::new(serde_json::from_value(value)?)),
+        "list" => {
+            let items = value.as_array().context("list tag must hold an array")?;
+            Value::List(items.iter().map(from_typed_json).collect::<Result<_>>()?)
+        }
+        "compound" => {
+            let map = value
+                .as_object()
+                .context("compound tag must hold an object")?;
+            Value::Compound(
+                map.iter()
+                    .map(|(k, v)| Ok((k.clone(), from_typed_json(v)?)))
+                    .collect::<Result<_>>()?,
+            )
+        }
+        other => bail!("unknown tag type {other}"),
+    })
+}
+
+/// One exported line: a chunk, block entity or entity and the chunk it
+/// belongs to.
+struct Record {
+    x: i32,
+    z: i32,
+    data: Compound,
+}
+
+fn write_record(
+    out: &mut impl Write,
+    format: &NbtFormat,
+    x: i32,
+    z: i32,
+    data: &Compound,
+) -> Result<()> {
+    match format {
+        NbtFormat::Snbt => writeln!(out, "{{x:{x},z:{z},data:{}}}", snbt::compound_to_snbt(data))?,
+        NbtFormat::Json => writeln!(
+            out,
+            "{}",
+            json!({ "x": x, "z": z, "data": typed_compound(data) })
+        )?,
+    }
+    Ok(())
+}
+
+fn read_record(line: &str, format: &NbtFormat) -> Result<Record> {
+    let (x, z, data) = match format {
+        NbtFormat::Snbt => {
+            let Value::Compound(mut record) = snbt::parse(line)? else {
+                bail!("expected a compound");
+            };
+            (
+                get_int(&record, "x"),
+                get_int(&record, "z"),
+                record.remove("data"),
+            )
+        }
+        NbtFormat::Json => {
+            let record: serde_json::Value = serde_json::from_str(line)?;
+            let coord = |key: &str| record[key].as_i64().and_then(|n| i32::try_from(n).ok());
+            let data = match record.get("data") {
+                Some(data) => Some(from_typed_json(data)?),
+                None => None,
+            };
+            (coord("x"), coord("z"), data)
+        }
+    };
+    match (x, z, data) {
+        (Some(x), Some(z), Some(Value::Compound(data))) => Ok(Record { x, z, data }),
+        _ => bail!("expected x, z and a data compound"),
+    }
+}
+
+pub(crate) fn export(args: &ExportArgs) -> Result<()> {
+    let mut out: Box<dyn Write> = match &args.output {
+        Some(path) => Box::new(BufWriter::new(File::create(path)?)),
+        None => Box::new(BufWriter::new(io::stdout().lock())),
+    };
+    let from = args.from.as_ref();
+    let to = args.to.as_ref();
+
+    match args.kind {
+        ExportKind::Chunks => {
+            let region_dir = dimension_dir(&args.world, &args.dimension).join("region");
+            let region_files = list_region_files(&region_dir)?;
+            for_each_chunk(&region_files, from, to, |x, z, chunk| {
+                write_record(&mut out, &args.format, x, z, chunk)
+            })?;
+        }
+        ExportKind::BlockEntities => {
+            for_each_block_entity(&args.world, &args.dimension, from, to, |pos, entity| {
+                let Value::Compound(entity) = entity else {
+                    return Ok(());
+                };
+                write_record(&mut out, &args.format, pos.0 >> 4, pos.2 >> 4, entity)
+            })?;
+        }
+        ExportKind::Entities => {
+            for_each_entity(&args.world, &args.dimension, from, to, |(x, z), entity| {
+                write_record(&mut out, &args.format, x, z, entity)
+            })?;
+        }
+    }
+    out.flush()?;
+    Ok(())
+}
+
+/// Replaces the item of `list` for which `same` is true, or appends it
+fn replace_or_push(list: &mut Vec<Value>, item: Compound, same: impl Fn(&Compound) -> bool) {
+    let existing = list.iter_mut().find(|v| match v {
+        Value::Compound(c) => same(c),
+        _ => false,
+    });
+    match existing {
+        Some(v) => *v = Value::Compound(item),
+        None => list.push(Value::Compound(item)),
+    }
+}
+
+/// Records to import, grouped by chunk
+type ChunkRecords = BTreeMap<(i32, i32), Vec<Compound>>;
+
+/// Merges the imported records of one chunk into it. `chunk` is `None`
+/// when the region has nothing in that slot yet; entities are then put in
+/// a new chunk of the given data version.
+fn merge(
+    kind: &ExportKind,
+    x: i32,
+    z: i32,
+    chunk: Option<Compound>,
+    records: Vec<Compound>,
+    data_version: Option<i32>,
+) -> Result<Compound> {
+    if let ExportKind::Chunks = kind {
+        let Some(data) = records.into_iter().last() else {
+            bail!("no data for chunk {x} {z}");
+        };
+        if let (Some(x_pos), Some(z_pos)) = (get_int(&data, "xPos"), get_int(&data, "zPos")) {
+            if (x_pos, z_pos) != (x, z) {
+                bail!("chunk data for {x} {z} says it belongs at {x_pos} {z_pos}");
+            }
+        }
+        return Ok(data);
+    }
+
+    let mut chunk = match (chunk, kind) {
+        (Some(chunk), _) => chunk,
+        (None, ExportKind::Entities) => {
+            let Some(data_version) = data_version else {
+                bail!(
+                    "can't create the entities chunk {x} {z} without the DataVersion of level.dat"
+                );
+            };
+            Compound::from([
+                ("DataVersion".to_string(), Value::Int(data_version)),
+                (
+                    "Position".to_string(),
+                    Value::IntArray(IntArray::new(vec![x, z])),
+                ),
+                ("Entities".to_string(), Value::List(vec![])),
+            ])
+        }
+        (None, _) => bail!("there's no chunk at {x} {z} to import into"),
+    };
+    let (list_key, id_keys): (&str, &[&str]) = match kind {
+        ExportKind::BlockEntities => ("block_entities", &["x", "y", "z"]),
+        _ => ("Entities", &["UUID"]),
+    };
+    let list = chunk
+        .entry(list_key.to_string())
+        .or_insert_with(|| Value::List(vec![]));
+    let Value::List(list) = list else {
+        bail!("{list_key} in chunk {x} {z} is not a list");
+    };
+    for record in records {
+        if id_keys.iter().any(|k| !record.contains_key(*k)) {
+            bail!(
+                "{list_key} entry in chunk {x} {z} lacks {}",
+                id_keys.join("/")
+            );
+        }
+        let key: Vec<Value> = id_keys.iter().map(|k| record[*k].clone()).collect();
+        replace_or_push(list, record, |c| {
+            id_keys
+                .iter()
+                .zip(key.iter())
+                .all(|(k, v)| c.get(*k) == Some(v))
+        });
+    }
+    Ok(chunk)
+}
+
+/// Merges the records into the chunks of a region file, returning the
+/// serialized chunks by slot
+fn merge_region(
+    path: &Path,
+    kind: &ExportKind,
+    chunks: ChunkRecords,
+    data_version: Option<i32>,
+) -> Result<Vec<(usize, usize, Vec<u8>)>> {
+    let mut reg = if path.exists() && fs::metadata(path)?.len() > 0 {
+        Some(Region::from_stream(File::open(path)?)?)
+    } else {
+        None
+    };
+    let mut merged = vec![];
+    for ((x, z), records) in chunks {
+        let (rx, rz) = (x.rem_euclid(32) as usize, z.rem_euclid(32) as usize);
+        let data = match reg.as_mut() {
+            Some(reg) => reg.read_chunk(rx, rz)?,
+            None => None,
+        };
+        let chunk: Option<Compound> = data.map(|data| from_bytes(&data)).transpose()?;
+        let chunk = merge(kind, x, z, chunk, records, data_version)
+            .with_context(|| format!("importing into {}", path.display()))?;
+        merged.push((rx, rz, to_bytes(&chunk)?));
+    }
+    Ok(merged)
+}
+
+fn import_region(path: &Path, chunks: &[(usize, usize, Vec<u8>)], backup: &Backup) -> Result<()> {
+    backup.save(path)?;
+    let mut reg = open_region_for_writing(path)?;
+    for (rx, rz, data) in chunks {
+        reg.write_chunk(*rx, *rz, data)?;
+    }
+    let written: Vec<_> = chunks.iter().map(|(rx, rz, _)| (*rx, *rz)).collect();
+    close_region(reg, &written)?;
+    backup.record(path, format!("imported {} chunks", chunks.len()))?;
+    log::info!("imported {} chunks into {}", chunks.len(), path.display());
+    Ok(())
+}
+
+pub(crate) fn import(args: &ImportArgs, backup: &Backup) -> Result<()> {
+    let input: Box<dyn BufRead> = match &args.input {
+        Some(path) => Box::new(BufReader::new(File::open(path)?)),
+        None => Box::new(io::stdin().lock()),
+    };
+
+    // records grouped by region, then by chunk
+    let mut regions: BTreeMap<(i32, i32), ChunkRecords> = BTreeMap::new();
+    for (n, line) in input.lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let record =
+            read_record(&line, &args.format).with_context(|| format!("reading line {}", n + 1))?;
+        regions
+            .entry((record.x >> 5, record.z >> 5))
+            .or_default()
+            .entry((record.x, record.z))
+            .or_default()
+            .push(record.data);
+    }
+
+    let dir = dimension_dir(&args.world, &args.dimension).join(match args.kind {
+        ExportKind::Entities => "entities",
+        _ => "region",
+    });
+    // entities chunks are created as needed, with the world's version
+    let data_version = match args.kind {
+        ExportKind::Entities => read_dat(&args.world.join("level.dat"))
+            .ok()
+            .and_then(|level| match level.get("Data") {
+                Some(Value::Compound(data)) => get_int(data, "DataVersion"),
+                _ => None,
+            }),
+        _ => None,
+    };
+    // every record is merged before anything is written, so a bad one
+    // leaves the world as it was
+    let mut merged = vec![];
+    for ((reg_x, reg_z), chunks) in regions {
+        let path = dir.join(format!("r.{reg_x}.{reg_z}.mca"));
+        let chunks = merge_region(&path, &args.kind, chunks, data_version)?;
+        merged.push((path, chunks));
+    }
+    for (path, chunks) in merged {
+        import_region(&path, &chunks, backup)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn typed_json_round_trip() {
+        let value = snbt::parse(
+            r#"{a:1b,b:2s,c:3,d:4L,e:0.1f,f:0.2d,g:"x",h:[B;1b],i:[I;2],j:[L;3L],k:[[1],[2]],l:{}}"#,
+        )
+        .unwrap();
+        let json = to_typed_json(&value);
+        assert_eq!(json["compound"]["e"], json!({ "float": 0.1f32 }));
+        assert_eq!(from_typed_json(&json).unwrap(), value);
+
+        let line = serde_json::to_string(&json!({ "x": -1, "z": 2, "data": json })).unwrap();
+        let record = read_record(&line, &NbtFormat::Json).unwrap();
+        assert_eq!((record.x, record.z), (-1, 2));
+        assert_eq!(Value::Compound(record.data), value);
+    }
+
+    #[test]
+    fn line_breaks_round_trip() {
+        let Value::Compound(book) = snbt::parse(
+            r#"{id:"minecraft:lectern",Book:{pages:["first line\nsecond\r\n\tindented \"quoted\" \\"]}}"#,
+        )
+        .unwrap() else {
+            unreachable!()
+        };
+        for format in [NbtFormat::Snbt, NbtFormat::Json] {
+            let mut out = vec![];
+            write_record(&mut out, &format, 1, -2, &book).unwrap();
+            let text = String::from_utf8(out).unwrap();
+            let lines: Vec<&str> = text.lines().collect();
+            assert_eq!(lines.len(), 1);
+            let record = read_record(lines[0], &format).unwrap();
+            assert_eq!((record.x, record.z), (1, -2));
+            assert_eq!(record.data, book);
+        }
+    }
+
+    #[test]
+    fn merge_entities() {
+        let Value::Compound(zombie) =
+            snbt::parse(r#"{id:"minecraft:zombie",UUID:[I;1,2,3,4],Health:20.0f}"#).unwrap()
+        else {
+            unreachable!()
+        };
+        let chunk = merge(
+            &ExportKind::Entities,
+            -1,
+            2,
+            None,
+            vec![zombie.clone()],
+            Some(3953),
+        )
+        .unwrap();
+        assert_eq!(
+            snbt::compound_to_snbt(&chunk),
+            r#"{DataVersion:3953,Entities:[{Health:20f,UUID:[I;1,2,3,4],id:"minecraft:zombie"}],Position:[I;-1,2]}"#
+        );
+
+        let mut healed = zombie.clone();
+        healed.insert("Health".to_string(), Value::Float(10.0));
+        let chunk = merge(
+            &ExportKind::Entities,
+            -1,
+            2,
+            Some(chunk),
+            vec![healed],
+            None,
+        )
+        .unwrap();
+        let Some(Value::List(entities)) = chunk.get("Entities") else {
+            panic!("no entities");
+        };
+        assert_eq!(entities.len(), 1);
+
+        assert!(merge(&ExportKind::Entities, 0, 0, None, vec![zombie], None).is_err());
+        assert!(merge(&ExportKind::BlockEntities, 0, 0, None, vec![], None).is_err());
+    }
+}
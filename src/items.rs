@@ -0,0 +1,280 @@
+use crate::cli::FindItemsArgs;
+use crate::entities::{entity_block, entity_id, for_each_entity};
+use crate::for_each_block_entity;
+use crate::nbt::Compound;
+use crate::selector::NameMatcher;
+
+use anyhow::Result;
+use fastnbt::Value;
+use serde::Serialize;
+
+/// Keys holding an item stack, or a list of them
+const ITEM_KEYS: &[&str] = &[
+    "Items",
+    "Item",
+    "item",
+    "Inventory",
+    "EnderItems",
+    "HandItems",
+    "ArmorItems",
+    "SaddleItem",
+    "ArmorItem",
+    "DecorItem",
+    "RecordItem",
+    "Book",
+    "body_armor_item",
+    "minecraft:bundle_contents",
+    "minecraft:charged_projectiles",
+];
+
+/// Keys holding items that aren't really there, like the ones a villager
+/// trades
+const SKIPPED_KEYS: &[&str] = &["Offers"];
+
+#[derive(Serialize, Debug)]
+pub(crate) struct FoundItem {
+    pub(crate) id: String,
+    pub(crate) count: i64,
+    /// path from the container to the item, like
+    /// `Items[3].components."minecraft:container"[0].item`
+    pub(crate) path: String,
+    /// ids of the items the item is nested in, like a shulker box
+    pub(crate) inside: Vec<String>,
+}
+
+pub(crate) fn item_count(item: &Compound) -> i64 {
+    match item.get("count").or_else(|| item.get("Count")) {
+        Some(Value::Byte(n)) => *n as i64,
+        Some(Value::Short(n)) => *n as i64,
+        Some(Value::Int(n)) => *n as i64,
+        Some(Value::Long(n)) => *n,
+        _ => 1,
+    }
+}
+
+fn path_key(key: &str) -> String {
+    if key.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+        key.to_string()
+    } else {
+        format!("{key:?}")
+    }
+}
+
+/// Collects the item stacks found anywhere in `value`. A compound is
+/// considered an item stack when it has an `id` and a count, or when it's
+/// stored under one of the usual item keys.
+fn find_items(
+    value: &Value,
+    is_item: bool,
+    path: &str,
+    inside: &mut Vec<String>,
+    found: &mut Vec<FoundItem>,
+) {
+    match value {
+        Value::Compound(c) => {
+            let is_item = is_item || c.contains_key("count") || c.contains_key("Count");
+            let item_id = match c.get("id") {
+                Some(Value::String(id)) if is_item => Some(id),
+                _ => None,
+            };
+            if let Some(id) = item_id {
+                if id == "minecraft:air" {
+                    return;
+                }
+                found.push(FoundItem {
+                    id: id.clone(),
+                    count: item_count(c),
+                    path: path.to_string(),
+                    inside: inside.clone(),
+                });
+                inside.push(id.clone());
+            }
+            for (key, child) in c {
+                if SKIPPED_KEYS.contains(&key.as_str()) {
+                    continue;
+                }
+                let child_path = format!("{path}.{}", path_key(key));
+                let child_is_item = ITEM_KEYS.contains(&key.as_str())
+                    // `equipment` maps slot names to items
+                    || path.ends_with("equipment");
+                find_items(child, child_is_item, &child_path, inside, found);
+            }
+            if item_id.is_some() {
+                inside.pop();
+            }
+        }
+        Value::List(list) => {
+            for (i, child) in list.iter().enumerate() {
+                find_items(child, is_item, &format!("{path}[{i}]"), inside, found);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Item stacks held by a block entity or an entity, including the ones
+/// nested in other items like shulker boxes and bundles
+pub(crate) fn container_items(container: &Compound) -> Vec<FoundItem> {
+    let mut found = vec![];
+    for (key, value) in container {
+        if SKIPPED_KEYS.contains(&key.as_str()) {
+            continue;
+        }
+        let is_item = ITEM_KEYS.contains(&key.as_str());
+        find_items(value, is_item, &path_key(key), &mut vec![], &mut found);
+    }
+    found
+}
+
+#[derive(Serialize, Debug)]
+struct ItemLocation<'a> {
+    x: i32,
+    y: i32,
+    z: i32,
+    container: &'a str,
+    #[serde(flatten)]
+    item: &'a FoundItem,
+}
+
+pub(crate) fn find_items_command(args: &FindItemsArgs) -> Result<()> {
+    let matcher = args.item.as_deref().map(NameMatcher::parse).transpose()?;
+    let mut total = 0;
+
+    let mut report = |pos: (i32, i32, i32), container: &Compound| -> Result<()> {
+        let container_id = entity_id(container).unwrap_or("unknown");
+        for item in container_items(container).iter() {
+            if !matcher.as_ref().is_none_or(|m| m.matches(&item.id)) {
+                continue;
+            }
+            total += item.count;
+            if args.json {
+                let location = ItemLocation {
+                    x: pos.0,
+                    y: pos.1,
+                    z: pos.2,
+                    container: container_id,
+                    item,
+                };
+                println!("{}", serde_json::to_string(&location)?);
+            } else {
+                println!(
+                    "{} {} {} {container_id} {} {} {}",
+                    pos.0, pos.1, pos.2, item.path, item.id, item.count
+                );
+            }
+        }
+        Ok(())
+    };
+
+    for_each_block_entity(
+        &args.world,
+        &args.dimension,
+        args.from.as_ref(),
+        args.to.as_ref(),
+        |pos, block_entity| match block_entity {
+            Value::Compound(block_entity) => report(pos, block_entity),
+            _ => Ok(()),
+        },
+    )?;
+
+    if !args.no_entities {
+        for_each_entity(
+            &args.world,
+            &args.dimension,
+            args.from.as_ref(),
+            args.to.as_ref(),
+            |_, entity| {
+                let Some(pos) = entity_block(entity) else {
+                    return Ok(());
+                };
+                report(pos, entity)
+            },
+        )?;
+    }
+
+    log::info!("{total} items found.");
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn compound<const N: usize>(entries: [(&str, Value); N]) -> Value {
+        Value::Compound(
+            entries
+                .into_iter()
+                .map(|(k, v)| (k.to_string(), v))
+                .collect(),
+        )
+    }
+
+    #[test]
+    fn nested_items() {
+        let elytra = compound([("id", Value::String("minecraft:elytra".into()))]);
+        let shulker = compound([
+            ("Slot", Value::Byte(1)),
+            ("id", Value::String("minecraft:shulker_box".into())),
+            ("count", Value::Int(1)),
+            (
+                "components",
+                compound([(
+                    "minecraft:container",
+                    Value::List(vec![compound([("slot", Value::Int(0)), ("item", elytra)])]),
+                )]),
+            ),
+        ]);
+        let dirt = compound([
+            ("Slot", Value::Byte(0)),
+            ("id", Value::String("minecraft:dirt".into())),
+            ("Count", Value::Byte(64)),
+        ]);
+        let Value::Compound(chest) = compound([
+            ("id", Value::String("minecraft:chest".into())),
+            ("Items", Value::List(vec![dirt, shulker])),
+        ]) else {
+            unreachable!()
+        };
+
+        let mut found = container_items(&chest);
+        found.sort_by(|a, b| a.path.cmp(&b.path));
+
+        assert_eq!(found.len(), 3);
+        assert_eq!(found[0].id, "minecraft:dirt");
+        assert_eq!(found[0].count, 64);
+        assert_eq!(found[1].id, "minecraft:shulker_box");
+        assert_eq!(found[2].id, "minecraft:elytra");
+        assert_eq!(found[2].count, 1);
+        assert_eq!(
+            found[2].path,
+            "Items[1].components.\"minecraft:container\"[0].item"
+        );
+        assert_eq!(found[2].inside, vec!["minecraft:shulker_box"]);
+    }
+
+    #[test]
+    fn villager_offers() {
+        let item =
+            |id: &str| compound([("id", Value::String(id.into())), ("count", Value::Int(1))]);
+        let Value::Compound(villager) = compound([
+            ("id", Value::String("minecraft:villager".into())),
+            ("Inventory", Value::List(vec![item("minecraft:wheat")])),
+            (
+                "Offers",
+                compound([(
+                    "Recipes",
+                    Value::List(vec![compound([
+                        ("buy", item("minecraft:emerald")),
+                        ("sell", item("minecraft:bread")),
+                    ])]),
+                )]),
+            ),
+        ]) else {
+            unreachable!()
+        };
+
+        let found = container_items(&villager);
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].id, "minecraft:wheat");
+    }
+}
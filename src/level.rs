@@ -0,0 +1,370 @@
+use crate::backup::Backup;
+use crate::cli::{Difficulty, LevelArgs};
+use crate::dat::{read_dat, write_dat};
+use crate::nbt::{get_double, get_int, get_long, get_string, Compound};
+
+use anyhow::{bail, Context, Result};
+use fastnbt::{IntArray, Value};
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+const DIFFICULTIES: [&str; 4] = ["peaceful", "easy", "normal", "hard"];
+
+#[derive(Serialize, Debug)]
+struct WorldBorder {
+    center_x: Option<f64>,
+    center_z: Option<f64>,
+    size: Option<f64>,
+}
+
+#[derive(Serialize, Debug)]
+struct LevelInfo {
+    name: Option<String>,
+    seed: Option<i64>,
+    data_version: Option<i32>,
+    version: Option<String>,
+    spawn: Option<(i32, i32, i32)>,
+    difficulty: Option<String>,
+    difficulty_locked: Option<bool>,
+    world_border: WorldBorder,
+    time: Option<i64>,
+    day_time: Option<i64>,
+    raining: Option<bool>,
+    thundering: Option<bool>,
+    game_rules: BTreeMap<String, String>,
+    enabled_datapacks: Vec<String>,
+    disabled_datapacks: Vec<String>,
+}
+
+fn compound<'a>(c: &'a Compound, key: &str) -> Option<&'a Compound> {
+    match c.get(key)? {
+        Value::Compound(c) => Some(c),
+        _ => None,
+    }
+}
+
+fn strings(c: &Compound, key: &str) -> Vec<String> {
+    let Some(Value::List(list)) = c.get(key) else {
+        return vec![];
+    };
+    list.iter()
+        .filter_map(|v| match v {
+            Value::String(s) => Some(s.clone()),
+            _ => None,
+        })
+        .collect()
+}
+
+fn value_text(value: &Value) -> String {
+    match value {
+        Value::String(s) => s.clone(),
+        Value::Byte(n) => (*n != 0).to_string(),
+        Value::Int(n) => n.to_string(),
+        Value::Long(n) => n.to_string(),
+        other => format!("{other:?}"),
+    }
+}
+
+fn level_info(data: &Compound) -> LevelInfo {
+    // the seed moved into WorldGenSettings in 1.16
+    let seed = compound(data, "WorldGenSettings")
+        .and_then(|settings| get_long(settings, "seed"))
+        .or_else(|| get_long(data, "RandomSeed"));
+
+    // since 1.21.9 the spawn is a compound; worlds converted from older
+    // versions may still have the old keys around
+    let spawn = match compound(data, "spawn").and_then(|s| s.get("pos")) {
+        Some(Value::IntArray(pos)) if pos.len() == 3 => Some((pos[0], pos[1], pos[2])),
+        _ => match (
+            get_int(data, "SpawnX"),
+            get_int(data, "SpawnY"),
+            get_int(data, "SpawnZ"),
+        ) {
+            (Some(x), Some(y), Some(z)) => Some((x, y, z)),
+            _ => None,
+        },
+    };
+
+    let datapacks = compound(data, "DataPacks");
+    LevelInfo {
+        name: get_string(data, "LevelName"),
+        seed,
+        data_version: get_int(data, "DataVersion"),
+        version: compound(data, "Version").and_then(|v| get_string(v, "Name")),
+        spawn,
+        difficulty: get_int(data, "Difficulty")
+            .and_then(|d| DIFFICULTIES.get(d as usize))
+            .map(|d| d.to_string()),
+        difficulty_locked: get_int(data, "DifficultyLocked").map(|l| l != 0),
+        world_border: WorldBorder {
+            center_x: get_double(data, "BorderCenterX"),
+            center_z: get_double(data, "BorderCenterZ"),
+            size: get_double(data, "BorderSize"),
+        },
+        time: get_long(data, "Time"),
+        day_time: get_long(data, "DayTime"),
+        raining: get_int(data, "raining").map(|r| r != 0),
+        thundering: get_int(data, "thundering").map(|t| t != 0),
+        game_rules: compound(data, "GameRules")
+            .map(|rules| {
+                rules
+                    .iter()
+                    .map(|(k, v)| (k.clone(), value_text(v)))
+                    .collect()
+            })
+            .unwrap_or_default(),
+        enabled_datapacks: datapacks.map(|d| strings(d, "Enabled")).unwrap_or_default(),
+        disabled_datapacks: datapacks
+            .map(|d| strings(d, "Disabled"))
+            .unwrap_or_default(),
+    }
+}
+
+fn print_info(info: &LevelInfo) {
+    fn show<T: std::fmt::Display>(value: &Option<T>) -> String {
+        match value {
+            Some(v) => v.to_string(),
+            None => "?".to_string(),
+        }
+    }
+
+    println!("name: {}", show(&info.name));
+    println!("seed: {}", show(&info.seed));
+    println!(
+        "version: {} (data version {})",
+        show(&info.version),
+        show(&info.data_version)
+    );
+    match info.spawn {
+        Some((x, y, z)) => println!("spawn: {x} {y} {z}"),
+        None => println!("spawn: ?"),
+    }
+    println!(
+        "difficulty: {}{}",
+        show(&info.difficulty),
+        if info.difficulty_locked == Some(true) {
+            " (locked)"
+        } else {
+            ""
+        }
+    );
+    println!(
+        "world border: center {} {}, size {}",
+        show(&info.world_border.center_x),
+        show(&info.world_border.center_z),
+        show(&info.world_border.size)
+    );
+    println!(
+        "time: {} (day time {})",
+        show(&info.time),
+        show(&info.day_time)
+    );
+    println!(
+        "weather: raining {}, thundering {}",
+        show(&info.raining),
+        show(&info.thundering)
+    );
+    println!("enabled datapacks: {}", info.enabled_datapacks.join(", "));
+    println!("disabled datapacks: {}", info.disabled_datapacks.join(", "));
+    println!("game rules:");
+    for (rule, value) in info.game_rules.iter() {
+        println!("  {rule} {value}");
+    }
+}
+
+/// Parses a game rule value in the same form as `old`, a boolean or an
+/// integer, whether it's typed or, as older versions store every rule, a
+/// string
+fn game_rule_value(rule: &str, old: &Value, value: &str) -> Result<Value> {
+    let is_bool = |v: &str| v == "true" || v == "false";
+    let new = match old {
+        Value::Byte(_) if is_bool(value) => Value::Byte((value == "true") as i8),
+        Value::Int(_) => match value.parse() {
+            Ok(n) => Value::Int(n),
+            Err(_) => bail!("game rule {rule} takes an integer, found {value}"),
+        },
+        Value::String(old) if is_bool(old) && is_bool(value) => Value::String(value.to_string()),
+        Value::String(old) if old.parse::<i32>().is_ok() => match value.parse::<i32>() {
+            Ok(_) => Value::String(value.to_string()),
+            Err(_) => bail!("game rule {rule} takes an integer, found {value}"),
+        },
+        Value::Byte(_) | Value::String(_) => {
+            bail!("game rule {rule} takes true or false, found {value}")
+        }
+        _ => bail!("game rule {rule} has an unexpected type"),
+    };
+    Ok(new)
+}
+
+/// Sets a game rule, keeping the form already used by the world. Rules the
+/// world doesn't have are refused, since they're most likely typos, unless
+/// `force` is set.
+fn set_game_rule(data: &mut Compound, rule: &str, value: &str, force: bool) -> Result<()> {
+    let rules = match data
+        .entry("GameRules".to_string())
+        .or_insert_with(|| Value::Compound(Compound::new()))
+    {
+        Value::Compound(rules) => rules,
+        _ => bail!("GameRules is not a compound"),
+    };
+    let new = match rules.get(rule) {
+        Some(old) => game_rule_value(rule, old, value)?,
+        None if !force => bail!("the world has no game rule {rule}, use --force to add it"),
+        // typed like the rules already there, if they are
+        None if rules.values().any(|v| !matches!(v, Value::String(_))) => {
+            match value.parse::<i32>() {
+                Ok(n) => Value::Int(n),
+                Err(_) => game_rule_value(rule, &Value::Byte(0), value)?,
+            }
+        }
+        None => Value::String(value.to_string()),
+    };
+    rules.insert(rule.to_string(), new);
+    Ok(())
+}
+
+/// Applies the requested edits, returning a description of each
+fn edit(data: &mut Compound, args: &LevelArgs) -> Result<Vec<String>> {
+    let mut changes = vec![];
+
+    for rule in args.gamerule.iter() {
+        let (rule, value) = rule
+            .split_once('=')
+            .with_context(|| format!("expected rule=value, found {rule}"))?;
+        set_game_rule(data, rule, value, args.force)?;
+        changes.push(format!("set game rule {rule} to {value}"));
+    }
+
+    if let Some((x, y, z)) = args.spawn {
+        match data.get_mut("spawn") {
+            Some(Value::Compound(spawn)) => {
+                spawn.insert(
+                    "pos".to_string(),
+                    Value::IntArray(IntArray::new(vec![x, y, z])),
+                );
+            }
+            _ => {
+                data.insert("SpawnX".to_string(), Value::Int(x));
+                data.insert("SpawnY".to_string(), Value::Int(y));
+                data.insert("SpawnZ".to_string(), Value::Int(z));
+            }
+        }
+        changes.push(format!("set spawn to {x} {y} {z}"));
+    }
+
+    if let Some(ref difficulty) = args.difficulty {
+        let value = match difficulty {
+            Difficulty::Peaceful => 0,
+            Difficulty::Easy => 1,
+            Difficulty::Normal => 2,
+            Difficulty::Hard => 3,
+        };
+        data.insert("Difficulty".to_string(), Value::Byte(value));
+        changes.push(format!(
+            "set difficulty to {}",
+            DIFFICULTIES[value as usize]
+        ));
+    }
+
+    if let Some(size) = args.border_size {
+        data.insert("BorderSize".to_string(), Value::Double(size));
+        data.insert("BorderSizeLerpTarget".to_string(), Value::Double(size));
+        data.insert("BorderSizeLerpTime".to_string(), Value::Long(0));
+        changes.push(format!("set world border size to {size}"));
+    }
+
+    if let Some((x, z)) = args.border_center {
+        data.insert("BorderCenterX".to_string(), Value::Double(x as f64));
+        data.insert("BorderCenterZ".to_string(), Value::Double(z as f64));
+        changes.push(format!("set world border center to {x} {z}"));
+    }
+
+    Ok(changes)
+}
+
+pub(crate) fn level(args: &LevelArgs, backup: &Backup) -> Result<()> {
+    let path = args.world.join("level.dat");
+    let mut level = read_dat(&path)?;
+    let Some(Value::Compound(data)) = level.get_mut("Data") else {
+        bail!("{} has no Data compound", path.display());
+    };
+
+    let changes = edit(data, args)?;
+    if changes.is_empty() {
+        let info = level_info(data);
+        if args.json {
+            println!("{}", serde_json::to_string(&info)?);
+        } else {
+            print_info(&info);
+        }
+        return Ok(());
+    }
+
+    backup.save(&path)?;
+    write_dat(&path, &level)?;
+    for change in changes {
+        log::info!("{change}");
+        backup.record(&path, change)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cli::{Action, MclArgs};
+    use crate::snbt;
+    use clap::Parser;
+
+    fn set_spawn(data: &str) -> Compound {
+        let Value::Compound(mut data) = snbt::parse(data).unwrap() else {
+            unreachable!()
+        };
+        let args = MclArgs::parse_from(["mcl", "level", "--world", "w", "--spawn", "1,2,3"]);
+        let Some(Action::Level(args)) = args.action else {
+            unreachable!()
+        };
+        edit(&mut data, &args).unwrap();
+        data
+    }
+
+    #[test]
+    fn spawn_layouts() {
+        let data = set_spawn("{SpawnX:0,SpawnY:64,SpawnZ:0}");
+        assert_eq!(level_info(&data).spawn, Some((1, 2, 3)));
+
+        let data = set_spawn(r#"{spawn:{dimension:"minecraft:overworld",pos:[I;0,64,0]}}"#);
+        assert_eq!(level_info(&data).spawn, Some((1, 2, 3)));
+        assert!(!data.contains_key("SpawnX"));
+    }
+
+    #[test]
+    fn game_rules() {
+        let Value::Compound(mut data) = snbt::parse(
+            r#"{GameRules:{keepInventory:0b,randomTickSpeed:3,
+                "minecraft:old_style":"false",spawnRadius:"10"}}"#,
+        )
+        .unwrap() else {
+            unreachable!()
+        };
+        set_game_rule(&mut data, "keepInventory", "true", false).unwrap();
+        set_game_rule(&mut data, "randomTickSpeed", "10", false).unwrap();
+        set_game_rule(&mut data, "minecraft:old_style", "true", false).unwrap();
+        set_game_rule(&mut data, "spawnRadius", "0", false).unwrap();
+        assert!(set_game_rule(&mut data, "keepInventory", "1", false).is_err());
+        assert!(set_game_rule(&mut data, "randomTickSpeed", "true", false).is_err());
+        assert!(set_game_rule(&mut data, "minecraft:old_style", "yes", false).is_err());
+        assert!(set_game_rule(&mut data, "spawnRadius", "false", false).is_err());
+        // a typo
+        assert!(set_game_rule(&mut data, "keepInvetory", "true", false).is_err());
+        set_game_rule(&mut data, "newRule", "true", true).unwrap();
+        assert_eq!(
+            Value::Compound(data),
+            snbt::parse(
+                r#"{GameRules:{keepInventory:1b,randomTickSpeed:10,
+                    "minecraft:old_style":"true",spawnRadius:"0",newRule:1b}}"#
+            )
+            .unwrap()
+        );
+    }
+}
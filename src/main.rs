@@ -1,109 +1,58 @@
-use anyhow::{Context, Result};
+use anyhow::Result;
 use clap::Parser;
 use cli::{Coords, Dimension, MclArgs};
 use fastanvil::Region;
-use fastnbt::{from_bytes, from_value, to_bytes, Value};
+use fastnbt::{from_value, Value};
 use nbt::load_chunk;
-use std::{
-    collections::{HashMap, HashSet},
-    fs::File,
-    io::Seek,
-    path::Path,
-};
+use std::{collections::HashMap, fs::File, path::Path};
 
+use crate::backup::Backup;
 use crate::nbt::BlockEntity;
-
+use crate::nbt_path::NbtPath;
+use crate::region::{dimension_dir, edit_chunks, region_coords, select_region_files};
+use crate::selector::BlockSelector;
+
+mod area;
+mod backup;
+mod blocks;
+mod check;
 mod cli;
+mod compact;
+mod copy;
+mod dat;
+mod diff;
 mod entities;
+mod export;
+mod items;
+mod level;
 mod nbt;
+mod nbt_path;
+mod player;
+mod players;
+mod protect;
 mod prune;
-
-fn reset_lighting(mut reg: Region<File>) -> Result<()> {
-    let mut new_chunks = vec![];
-
-    for raw_chunk in reg.iter() {
-        let raw_chunk = raw_chunk?;
-        let x = raw_chunk.x;
-        let z = raw_chunk.z;
-
-        let mut full_chunk: HashMap<String, Value> = from_bytes(raw_chunk.data.as_slice())?;
-
-        if full_chunk.remove("isLightOn").is_some() {
-            println!("removed isLightOn from chunk x={x} z={z}");
-        }
-
-        let Some(Value::List(sections)) = full_chunk.get_mut("sections") else {
-            continue;
-        };
-
-        for section in sections {
-            let Value::Compound(section) = section else {
-                continue;
-            };
-            if section.remove("BlockLight").is_some() {
-                println!("removed block light from chunk x={x} z={z}");
-            };
-            if section.remove("SkyLight").is_some() {
-                println!("removed sky light from chunk x={x} z={z}");
-            };
+mod region;
+mod render;
+mod repair;
+mod selector;
+mod snbt;
+
+fn reset_lighting(
+    paths: &[(&str, NbtPath)],
+    x: i32,
+    z: i32,
+    full_chunk: &mut HashMap<String, Value>,
+) -> Result<bool> {
+    let mut changed = false;
+
+    for (text, path) in paths {
+        if path.remove(full_chunk)? > 0 {
+            println!("removed {text} from chunk x={x} z={z}");
+            changed = true;
         }
-
-        new_chunks.push((raw_chunk.x, raw_chunk.z, full_chunk));
-    }
-
-    for (x, z, full_chunk) in new_chunks {
-        reg.write_chunk(x, z, to_bytes(&full_chunk)?.as_slice())?;
     }
 
-    let mut file = reg.into_inner()?;
-    let len = file.stream_position()?;
-    file.set_len(len)?;
-
-    Ok(())
-}
-
-fn blocks(mut reg: Region<File>, pattern: &str) -> Result<()> {
-    for raw_chunk in reg.iter() {
-        let raw_chunk = raw_chunk?;
-        // let x = raw_chunk.x;
-        // let z = raw_chunk.z;
-
-        // dump_nbt(raw_chunk.data.as_slice())?;
-
-        let chunk = load_chunk(raw_chunk.data.as_slice())?;
-        for section in chunk.sections() {
-            // println!("{:#?}", section);
-            let mut ids: HashSet<usize> = HashSet::new();
-            let Some(ref block_states) = section.block_states else {
-                continue;
-            };
-            for (id, block) in block_states.palette().iter().enumerate() {
-                if block.name() == "minecraft:air" {
-                    continue;
-                }
-                if block.name().contains(pattern) {
-                    ids.insert(id);
-                }
-            }
-
-            let indices = block_states.try_iter_indices();
-            if indices.is_none() {
-                continue;
-            }
-
-            for (i, palette_index) in indices.unwrap().enumerate() {
-                if !ids.contains(&palette_index) {
-                    continue;
-                }
-                let x = (raw_chunk.x << 4) + (i & 0x000F);
-                let y = ((section.y as isize) << 4) + ((i as isize & 0x0F00) >> 8);
-                let z = (raw_chunk.z << 4) + ((i & 0x00F0) >> 4);
-                let block = &block_states.palette()[palette_index];
-                println!("{x} {y} {z} {:#?}", block.name());
-            }
-        }
-    }
-    Ok(())
+    Ok(changed)
 }
 
 fn within_bounds(point: &Coords, from: Option<&Coords>, to: Option<&Coords>) -> bool {
@@ -170,32 +119,25 @@ fn rect_intersects_bounds(
     true
 }
 
-fn block_entities(
+/// Calls `f` with the position and compound of every block entity within
+/// the bounds
+fn for_each_block_entity(
     world: &Path,
-    dimension: Dimension,
-    from: Option<Coords>,
-    to: Option<Coords>,
-    json: bool,
+    dimension: &Dimension,
+    from: Option<&Coords>,
+    to: Option<&Coords>,
+    mut f: impl FnMut(Coords, &Value) -> Result<()>,
 ) -> Result<()> {
-    let region_dir = match dimension {
-        Dimension::Overworld => world.join("region"),
-        Dimension::Nether => world.join("DIM-1/region"),
-        Dimension::End => world.join("DIM1/region"),
-    };
+    let region_dir = dimension_dir(world, dimension).join("region");
     let region_files = prune::list_region_files(&region_dir)?;
     for reg_file in region_files {
         let file = File::options().read(true).write(false).open(&reg_file)?;
 
-        let stem = reg_file.file_stem().context("reading file stem")?;
-        let stem = stem.to_string_lossy();
-
-        let mut parts = stem.split('.').skip(1);
-        let reg_x: i32 = parts.next().context("parsing filename")?.parse()?;
-        let reg_z: i32 = parts.next().context("parsing filename")?.parse()?;
+        let (reg_x, reg_z) = region_coords(&reg_file)?;
 
         let rf = (reg_x * 512, reg_z * 512);
         let rt = (reg_x * 512 + 511, reg_z * 512 + 511);
-        if !rect_intersects_bounds(rf, rt, from.as_ref(), to.as_ref()) {
+        if !rect_intersects_bounds(rf, rt, from, to) {
             log::debug!("region {reg_x} {reg_z} doesn't intersect bounds, skipping");
             continue;
         }
@@ -217,7 +159,7 @@ fn block_entities(
 
             let cf = (x * 16, z * 16);
             let ct = (x * 16 + 15, z * 16 + 15);
-            if !rect_intersects_bounds(cf, ct, from.as_ref(), to.as_ref()) {
+            if !rect_intersects_bounds(cf, ct, from, to) {
                 log::debug!("chunk {x} {z} doesn't intersect bounds, skipping");
                 continue;
             }
@@ -233,21 +175,40 @@ fn block_entities(
             for entity in chunk.block_entities() {
                 let pos: BlockEntity = from_value(entity)?;
                 let pos = (pos.x, pos.y, pos.z);
-                if !within_bounds(&pos, from.as_ref(), to.as_ref()) {
+                if !within_bounds(&pos, from, to) {
                     log::debug!("entity {reg_x} {reg_z} doesn't intersect bounds, skipping");
                     continue;
                 }
-                if json {
-                    println!("{}", serde_json::to_string(&entity)?);
-                } else {
-                    println!("{:?}", entity);
-                }
+                f(pos, entity)?;
             }
         }
     }
     Ok(())
 }
 
+fn block_entities(
+    world: &Path,
+    dimension: Dimension,
+    from: Option<Coords>,
+    to: Option<Coords>,
+    json: bool,
+) -> Result<()> {
+    for_each_block_entity(
+        world,
+        &dimension,
+        from.as_ref(),
+        to.as_ref(),
+        |_, entity| {
+            if json {
+                println!("{}", serde_json::to_string(&entity)?);
+            } else {
+                println!("{:?}", entity);
+            }
+            Ok(())
+        },
+    )
+}
+
 fn main() -> Result<()> {
     env_logger::builder().format_timestamp_millis().init();
 
@@ -257,35 +218,45 @@ fn main() -> Result<()> {
         use cli::Action;
         match action {
             Action::Prune(prune_args) => {
-                prune::prune(
-                    &prune_args.world,
-                    prune_args.dimension,
-                    prune_args.inhabited_under,
-                    prune_args.buffer,
-                )?;
+                let backup = Backup::new(&args.backup_dir, "prune")?;
+                prune::prune(&prune_args, &backup)?;
+                backup.finish();
             }
-            // "blocks" => {
-            //     blocks(reg, "diamond")?;
-            // }
-            // _ => {
-            //     bail!("unknown action: {action}")
-            // }
             Action::ResetLighting(rl_args) => {
-                let file = File::options()
-                    .read(true)
-                    .write(true)
-                    .open(rl_args.region)?;
-                let reg = Region::from_stream(file)?;
-                reset_lighting(reg)?;
+                let paths = nbt_path::RESET_LIGHTING
+                    .iter()
+                    .map(|p| Ok((*p, NbtPath::parse(p)?)))
+                    .collect::<Result<Vec<_>>>()?;
+                let region_files = select_region_files(&rl_args.chunks)?;
+                let backup = Backup::new(&args.backup_dir, "reset-lighting")?;
+                let count = edit_chunks(
+                    &region_files,
+                    rl_args.chunks.from.as_ref(),
+                    rl_args.chunks.to.as_ref(),
+                    &backup,
+                    "reset lighting",
+                    &|x, z, chunk| reset_lighting(&paths, x, z, chunk),
+                )?;
+                log::info!("lighting reset in {count} chunks.");
+                backup.finish();
             }
             Action::Blocks(block_args) => {
-                let file = File::options()
-                    .read(true)
-                    .write(false)
-                    .open(&block_args.world)
-                    .with_context(|| format!("Failed to open `{}`", block_args.world.display()))?;
-                let reg = Region::from_stream(file)?;
-                blocks(reg, &block_args.pattern)?;
+                let selectors = block_args
+                    .pattern
+                    .iter()
+                    .map(|p| BlockSelector::parse(p, block_args.tags.as_deref()))
+                    .collect::<Result<Vec<_>>>()?;
+                if block_args.aggregate {
+                    blocks::aggregate(&block_args, &selectors)?;
+                } else {
+                    blocks::blocks(
+                        &block_args.world,
+                        block_args.dimension,
+                        &selectors,
+                        block_args.from,
+                        block_args.to,
+                    )?;
+                }
             }
             Action::BlockEntities(storage_args) => {
                 block_entities(
@@ -296,14 +267,69 @@ fn main() -> Result<()> {
                     storage_args.json,
                 )?;
             }
-            Action::Entities(storage_args) => {
-                entities::entities(
-                    &storage_args.world,
-                    storage_args.dimension,
-                    storage_args.from,
-                    storage_args.to,
-                    storage_args.json,
-                )?;
+            Action::Entities(entities_args) => {
+                entities::entities(&entities_args)?;
+            }
+            Action::Census(census_args) => {
+                entities::census(&census_args)?;
+            }
+            Action::FindItems(find_args) => {
+                items::find_items_command(&find_args)?;
+            }
+            Action::Players(players_args) => {
+                players::players(&players_args)?;
+            }
+            Action::Level(level_args) => {
+                let backup = Backup::new(&args.backup_dir, "level")?;
+                level::level(&level_args, &backup)?;
+                backup.finish();
+            }
+            Action::Nbt(nbt_args) => {
+                let backup = Backup::new(&args.backup_dir, "nbt")?;
+                nbt_path::nbt(&nbt_args, &backup)?;
+                backup.finish();
+            }
+            Action::Export(export_args) => {
+                export::export(&export_args)?;
+            }
+            Action::Import(import_args) => {
+                let backup = Backup::new(&args.backup_dir, "import")?;
+                export::import(&import_args, &backup)?;
+                backup.finish();
+            }
+            Action::Check(check_args) => {
+                if !check::check(&check_args)? {
+                    std::process::exit(1);
+                }
+            }
+            Action::Repair(repair_args) => {
+                let backup = Backup::new(&args.backup_dir, "repair")?;
+                repair::repair(&repair_args, &backup)?;
+                backup.finish();
+            }
+            Action::Compact(compact_args) => {
+                let backup = Backup::new(&args.backup_dir, "compact")?;
+                compact::compact(&compact_args, &backup)?;
+                backup.finish();
+            }
+            Action::CopyChunks(copy_args) => {
+                let backup = Backup::new(&args.backup_dir, "copy-chunks")?;
+                copy::copy_chunks_command(&copy_args, &backup)?;
+                backup.finish();
+            }
+            Action::RestoreArea(restore_args) => {
+                let backup = Backup::new(&args.backup_dir, "restore-area")?;
+                copy::restore_area(&restore_args, &backup)?;
+                backup.finish();
+            }
+            Action::Diff(diff_args) => {
+                diff::diff(&diff_args)?;
+            }
+            Action::Render(render_args) => {
+                render::render(&render_args)?;
+            }
+            Action::Restore(restore_args) => {
+                backup::restore(&restore_args.journal)?;
             }
         }
     }
@@ -1,8 +1,42 @@
 use fastanvil::Block;
 use fastanvil::BlockData;
 use fastnbt::error::Result;
-use fastnbt::{from_bytes, Value};
+use fastnbt::{from_bytes, LongArray, Value};
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+pub(crate) type Compound = HashMap<String, Value>;
+
+pub(crate) fn get_int(c: &Compound, key: &str) -> Option<i32> {
+    match c.get(key)? {
+        Value::Byte(n) => Some(*n as i32),
+        Value::Short(n) => Some(*n as i32),
+        Value::Int(n) => Some(*n),
+        _ => None,
+    }
+}
+
+pub(crate) fn get_long(c: &Compound, key: &str) -> Option<i64> {
+    match c.get(key)? {
+        Value::Long(n) => Some(*n),
+        _ => get_int(c, key).map(i64::from),
+    }
+}
+
+pub(crate) fn get_double(c: &Compound, key: &str) -> Option<f64> {
+    match c.get(key)? {
+        Value::Double(n) => Some(*n),
+        Value::Float(n) => Some(*n as f64),
+        _ => get_long(c, key).map(|n| n as f64),
+    }
+}
+
+pub(crate) fn get_string(c: &Compound, key: &str) -> Option<String> {
+    match c.get(key)? {
+        Value::String(s) => Some(s.clone()),
+        _ => None,
+    }
+}
 
 #[derive(Serialize, Deserialize, Debug)]
 pub(crate) struct BlockEntity {
@@ -16,6 +50,34 @@ pub struct Section {
     #[serde(rename = "Y")]
     pub(crate) y: i8,
     pub(crate) block_states: Option<BlockData<Block>>,
+    pub(crate) biomes: Option<Biomes>,
+}
+
+/// Biomes of a section (1.18+), stored for cells of 4x4x4 blocks
+#[derive(Deserialize, Debug)]
+pub(crate) struct Biomes {
+    palette: Vec<String>,
+    data: Option<LongArray>,
+}
+
+impl Section {
+    /// Biome at the given coordinates within the section
+    pub(crate) fn biome_at(&self, x: usize, y: usize, z: usize) -> Option<&str> {
+        let biomes = self.biomes.as_ref()?;
+        let (Some(data), 2..) = (&biomes.data, biomes.palette.len()) else {
+            return biomes.palette.first().map(String::as_str);
+        };
+
+        let bits = usize::BITS - (biomes.palette.len() - 1).leading_zeros();
+        let per_long = 64 / bits as usize;
+        let index = (y >> 2) * 16 + (z >> 2) * 4 + (x >> 2);
+        let long = *data.get(index / per_long)? as u64;
+        let palette_index = (long >> ((index % per_long) as u32 * bits)) & ((1u64 << bits) - 1);
+        biomes
+            .palette
+            .get(palette_index as usize)
+            .map(String::as_str)
+    }
 }
 
 #[derive(Deserialize, Debug)]
@@ -94,5 +156,3 @@ pub fn load_chunk(input: &[u8]) -> Result<Box<dyn Chunk>> {
         _ => Ok(Box::new(from_bytes::<Chunk117>(input)?)),
     }
 }
-
-
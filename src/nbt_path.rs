@@ -0,0 +1,419 @@
+use crate::backup::Backup;
+use crate::cli::{NbtAction, NbtArgs};
+use crate::nbt::Compound;
+use crate::region::{edit_chunks, for_each_chunk, select_region_files};
+use crate::snbt::{self, is_unquoted, Reader};
+
+use anyhow::{bail, Result};
+use fastnbt::Value;
+
+/// Paths removed from every chunk by `reset-lighting`
+pub(crate) const RESET_LIGHTING: [&str; 3] =
+    ["isLightOn", "sections[].BlockLight", "sections[].SkyLight"];
+
+#[derive(Debug)]
+enum Node {
+    /// `{...}`, only valid at the start of a path
+    Root(Compound),
+    /// `key` or `key{...}`
+    Key(String, Option<Compound>),
+    /// `[2]`, `[-1]` counts from the end
+    Index(i32),
+    /// `[]`
+    All,
+    /// `[{...}]`
+    Matching(Compound),
+}
+
+/// A path into an NBT value, written like the paths of Minecraft's `/data`
+/// command: `sections[].BlockLight`,
+/// `block_entities[{id:"minecraft:chest"}].Items[0].count`.
+#[derive(Debug)]
+pub(crate) struct NbtPath {
+    nodes: Vec<Node>,
+}
+
+/// Whether `c` has every tag in `filter`, compounds being compared the same
+/// way recursively
+fn matches_compound(filter: &Compound, c: &Compound) -> bool {
+    filter
+        .iter()
+        .all(|(key, expected)| match (expected, c.get(key)) {
+            (Value::Compound(f), Some(v)) => matches(f, v),
+            (expected, Some(v)) => expected == v,
+            (_, None) => false,
+        })
+}
+
+fn matches(filter: &Compound, value: &Value) -> bool {
+    match value {
+        Value::Compound(c) => matches_compound(filter, c),
+        _ => false,
+    }
+}
+
+/// Resolves a possibly negative index into a list of `len` items
+fn index(i: i32, len: usize) -> Option<usize> {
+    let i = if i < 0 {
+        len as i64 + i as i64
+    } else {
+        i as i64
+    };
+    (0..len as i64).contains(&i).then_some(i as usize)
+}
+
+fn get(nodes: &[Node], value: &Value, found: &mut Vec<Value>) {
+    let Some((node, rest)) = nodes.split_first() else {
+        found.push(value.clone());
+        return;
+    };
+    match (node, value) {
+        (Node::Root(filter), _) if matches(filter, value) => get(rest, value, found),
+        (Node::Key(key, filter), Value::Compound(c)) => {
+            if let Some(v) = c.get(key) {
+                if filter.as_ref().is_none_or(|f| matches(f, v)) {
+                    get(rest, v, found);
+                }
+            }
+        }
+        (Node::Index(i), Value::List(items)) => {
+            if let Some(i) = index(*i, items.len()) {
+                get(rest, &items[i], found);
+            }
+        }
+        (Node::Index(i), Value::ByteArray(a)) => {
+            if let Some(i) = index(*i, a.len()) {
+                get(rest, &Value::Byte(a[i]), found);
+            }
+        }
+        (Node::Index(i), Value::IntArray(a)) => {
+            if let Some(i) = index(*i, a.len()) {
+                get(rest, &Value::Int(a[i]), found);
+            }
+        }
+        (Node::Index(i), Value::LongArray(a)) => {
+            if let Some(i) = index(*i, a.len()) {
+                get(rest, &Value::Long(a[i]), found);
+            }
+        }
+        (Node::All, Value::List(items)) => {
+            for item in items {
+                get(rest, item, found);
+            }
+        }
+        (Node::Matching(filter), Value::List(items)) => {
+            for item in items.iter().filter(|item| matches(filter, item)) {
+                get(rest, item, found);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// What to do with the tags the path points at
+enum Edit<'a> {
+    Set(&'a Value),
+    Remove,
+}
+
+/// Applies `edit` to the tags `nodes` point at inside `value`, returning
+/// how many were changed. Like `/data modify`, setting a key creates the
+/// compounds missing along the way.
+fn apply(nodes: &[Node], value: &mut Value, edit: &Edit) -> Result<usize> {
+    let Some((node, rest)) = nodes.split_first() else {
+        bail!("empty path");
+    };
+
+    if !rest.is_empty() {
+        let mut count = 0;
+        match (node, value) {
+            (Node::Root(filter), value) if matches(filter, value) => {
+                count += apply(rest, value, edit)?;
+            }
+            (Node::Key(key, filter), Value::Compound(c)) => {
+                if filter.is_none() && matches!(edit, Edit::Set(_)) && !c.contains_key(key) {
+                    if let Node::Key(..) = rest[0] {
+                        c.insert(key.clone(), Value::Compound(Compound::new()));
+                    }
+                }
+                if let Some(v) = c.get_mut(key) {
+                    if filter.as_ref().is_none_or(|f| matches(f, v)) {
+                        count += apply(rest, v, edit)?;
+                    }
+                }
+            }
+            (Node::Index(i), Value::List(items)) => {
+                if let Some(i) = index(*i, items.len()) {
+                    count += apply(rest, &mut items[i], edit)?;
+                }
+            }
+            (Node::All, Value::List(items)) => {
+                for item in items {
+                    count += apply(rest, item, edit)?;
+                }
+            }
+            (Node::Matching(filter), Value::List(items)) => {
+                for item in items.iter_mut().filter(|item| matches(filter, item)) {
+                    count += apply(rest, item, edit)?;
+                }
+            }
+            _ => {}
+        }
+        return Ok(count);
+    }
+
+    let count = match (node, value, edit) {
+        (Node::Root(_), _, _) => bail!("can't replace or remove the root of a path"),
+        (Node::Key(key, filter), Value::Compound(c), Edit::Set(new)) => {
+            if filter
+                .as_ref()
+                .is_some_and(|f| !c.get(key).is_some_and(|v| matches(f, v)))
+            {
+                return Ok(0);
+            }
+            if c.get(key) == Some(*new) {
+                return Ok(0);
+            }
+            c.insert(key.clone(), (*new).clone());
+            1
+        }
+        (Node::Key(key, filter), Value::Compound(c), Edit::Remove) => match c.get(key) {
+            Some(v) if filter.as_ref().is_none_or(|f| matches(f, v)) => {
+                c.remove(key);
+                1
+            }
+            _ => 0,
+        },
+        (Node::Index(i), Value::List(items), edit) => match index(*i, items.len()) {
+            Some(i) => match edit {
+                Edit::Set(new) if items[i] == **new => 0,
+                Edit::Set(new) => {
+                    items[i] = (*new).clone();
+                    1
+                }
+                Edit::Remove => {
+                    items.remove(i);
+                    1
+                }
+            },
+            None => 0,
+        },
+        (Node::All, Value::List(items), Edit::Set(new)) => {
+            let mut count = 0;
+            for item in items.iter_mut().filter(|item| **item != **new) {
+                *item = (*new).clone();
+                count += 1;
+            }
+            count
+        }
+        (Node::All, Value::List(items), Edit::Remove) => {
+            let count = items.len();
+            items.clear();
+            count
+        }
+        (Node::Matching(filter), Value::List(items), Edit::Set(new)) => {
+            let mut count = 0;
+            for item in items
+                .iter_mut()
+                .filter(|item| matches(filter, item) && **item != **new)
+            {
+                *item = (*new).clone();
+                count += 1;
+            }
+            count
+        }
+        (Node::Matching(filter), Value::List(items), Edit::Remove) => {
+            let before = items.len();
+            items.retain(|item| !matches(filter, item));
+            before - items.len()
+        }
+        _ => 0,
+    };
+    Ok(count)
+}
+
+impl NbtPath {
+    pub(crate) fn parse(text: &str) -> Result<NbtPath> {
+        let mut reader = Reader::new(text);
+        let mut nodes = vec![];
+
+        reader.skip_whitespace();
+        if reader.peek() == Some('{') {
+            nodes.push(Node::Root(reader.compound()?));
+            if !reader.is_done() {
+                reader.expect('.')?;
+            }
+        }
+
+        while !reader.is_done() {
+            let key = if matches!(reader.peek(), Some('"' | '\'')) {
+                reader.quoted()?
+            } else {
+                reader.unquoted(|c| c != '.' && is_unquoted(c)).to_string()
+            };
+            if key.is_empty() {
+                bail!("expected a key in path {text}");
+            }
+            let filter = match reader.peek() {
+                Some('{') => Some(reader.compound()?),
+                _ => None,
+            };
+            nodes.push(Node::Key(key, filter));
+
+            while reader.eat('[') {
+                reader.skip_whitespace();
+                let node = match reader.peek() {
+                    Some(']') => Node::All,
+                    Some('{') => Node::Matching(reader.compound()?),
+                    _ => {
+                        let i = reader.unquoted(|c| c == '-' || c.is_ascii_digit());
+                        match i.parse() {
+                            Ok(i) => Node::Index(i),
+                            Err(_) => bail!("expected an index in path {text}"),
+                        }
+                    }
+                };
+                reader.expect(']')?;
+                nodes.push(node);
+            }
+
+            if !reader.is_done() {
+                reader.expect('.')?;
+            }
+        }
+
+        if nodes.is_empty() {
+            bail!("empty path");
+        }
+        Ok(NbtPath { nodes })
+    }
+
+    /// Every tag the path points at inside `root`
+    pub(crate) fn get(&self, root: &Compound) -> Vec<Value> {
+        let mut found = vec![];
+        let nodes = match self.nodes.split_first() {
+            Some((Node::Root(filter), rest)) if matches_compound(filter, root) => rest,
+            Some((Node::Root(_), _)) => return found,
+            _ => &self.nodes[..],
+        };
+        match nodes.split_first() {
+            Some((Node::Key(key, filter), rest)) => {
+                if let Some(v) = root.get(key) {
+                    if filter.as_ref().is_none_or(|f| matches(f, v)) {
+                        get(rest, v, &mut found);
+                    }
+                }
+            }
+            _ => found.push(Value::Compound(root.clone())),
+        }
+        found
+    }
+
+    fn apply(&self, root: &mut Compound, e: &Edit) -> Result<usize> {
+        let mut value = Value::Compound(std::mem::take(root));
+        let count = apply(&self.nodes, &mut value, e);
+        if let Value::Compound(c) = value {
+            *root = c;
+        }
+        count
+    }
+
+    /// Replaces, or creates, the tags the path points at. Returns how many
+    /// were changed.
+    pub(crate) fn set(&self, root: &mut Compound, value: &Value) -> Result<usize> {
+        self.apply(root, &Edit::Set(value))
+    }
+
+    /// Removes the tags the path points at, returning how many there were
+    pub(crate) fn remove(&self, root: &mut Compound) -> Result<usize> {
+        self.apply(root, &Edit::Remove)
+    }
+}
+
+pub(crate) fn nbt(args: &NbtArgs, backup: &Backup) -> Result<()> {
+    match &args.action {
+        NbtAction::Get(get_args) => {
+            let path = NbtPath::parse(&get_args.path)?;
+            let region_files = select_region_files(&get_args.chunks)?;
+            let chunks = &get_args.chunks;
+            for_each_chunk(
+                &region_files,
+                chunks.from.as_ref(),
+                chunks.to.as_ref(),
+                |x, z, chunk| {
+                    for value in path.get(chunk) {
+                        println!("{x} {z} {}", snbt::to_snbt(&value));
+                    }
+                    Ok(())
+                },
+            )?;
+        }
+        NbtAction::Set(set_args) => {
+            let path = NbtPath::parse(&set_args.path)?;
+            let value = snbt::parse(&set_args.value)?;
+            let chunks = &set_args.chunks;
+            let count = edit_chunks(
+                &select_region_files(chunks)?,
+                chunks.from.as_ref(),
+                chunks.to.as_ref(),
+                backup,
+                &format!("set {}", set_args.path),
+                &|_, _, chunk| Ok(path.set(chunk, &value)? > 0),
+            )?;
+            log::info!("{} set in {count} chunks", set_args.path);
+        }
+        NbtAction::Remove(remove_args) => {
+            let path = NbtPath::parse(&remove_args.path)?;
+            let chunks = &remove_args.chunks;
+            let count = edit_chunks(
+                &select_region_files(chunks)?,
+                chunks.from.as_ref(),
+                chunks.to.as_ref(),
+                backup,
+                &format!("remove {}", remove_args.path),
+                &|_, _, chunk| Ok(path.remove(chunk)? > 0),
+            )?;
+            log::info!("{} removed from {count} chunks", remove_args.path);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn paths() {
+        let Value::Compound(mut chunk) = snbt::parse(
+            r#"{isLightOn:1b,sections:[{Y:0b,SkyLight:[B;1b]},{Y:1b,BlockLight:[B;2b]}],
+                block_entities:[{id:"minecraft:chest",Items:[{id:"minecraft:stone",count:3}]},
+                                {id:"minecraft:furnace"}]}"#,
+        )
+        .unwrap() else {
+            panic!("expected a compound");
+        };
+
+        let path =
+            NbtPath::parse(r#"block_entities[{id:"minecraft:chest"}].Items[-1].count"#).unwrap();
+        assert_eq!(path.get(&chunk), vec![Value::Int(3)]);
+        assert_eq!(path.set(&mut chunk, &Value::Int(5)).unwrap(), 1);
+        assert_eq!(path.get(&chunk), vec![Value::Int(5)]);
+
+        for p in RESET_LIGHTING {
+            assert_eq!(NbtPath::parse(p).unwrap().remove(&mut chunk).unwrap(), 1);
+        }
+        assert!(!chunk.contains_key("isLightOn"));
+        assert!(NbtPath::parse("sections[].SkyLight")
+            .unwrap()
+            .get(&chunk)
+            .is_empty());
+
+        let path = NbtPath::parse("data.custom.owner").unwrap();
+        path.set(&mut chunk, &Value::String("me".to_string()))
+            .unwrap();
+        assert_eq!(path.get(&chunk), vec![Value::String("me".to_string())]);
+
+        assert!(NbtPath::parse("sections[x]").is_err());
+    }
+}
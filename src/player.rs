@@ -0,0 +1,133 @@
+use crate::nbt::{load_chunk, Chunk};
+use crate::region::region_coords;
+
+use anyhow::{Context, Result};
+use fastanvil::Region;
+use fastnbt::Value;
+use rayon::prelude::*;
+use std::collections::HashSet;
+use std::fs::{read_to_string, File};
+use std::path::{Path, PathBuf};
+
+/// Blocks that don't generate naturally, not even in structures, used when
+/// no `--player-blocks` file is given. Rails, torches, crafting tables and
+/// glass are left out since mineshafts, villages and strongholds have them,
+/// and jungle temples have redstone, repeaters and sticky pistons.
+const PLAYER_BLOCKS: &[&str] = &[
+    "minecraft:comparator",
+    "minecraft:piston",
+    "minecraft:observer",
+    "minecraft:nether_portal",
+    "minecraft:scaffolding",
+    "minecraft:white_concrete",
+    "minecraft:beacon",
+];
+
+/// Block entities that also generate naturally, so they say nothing about
+/// players having been around
+const NATURAL_BLOCK_ENTITIES: &[&str] = &[
+    "minecraft:mob_spawner",
+    "minecraft:trial_spawner",
+    "minecraft:vault",
+    "minecraft:beehive",
+    "minecraft:bee_nest",
+    "minecraft:brushable_block",
+    "minecraft:suspicious_sand",
+    "minecraft:suspicious_gravel",
+    "minecraft:sculk_sensor",
+    "minecraft:calibrated_sculk_sensor",
+    "minecraft:sculk_shrieker",
+    "minecraft:sculk_catalyst",
+    "minecraft:end_gateway",
+    "minecraft:end_portal",
+    "minecraft:decorated_pot",
+    "minecraft:bell",
+    "minecraft:bed",
+];
+
+/// Reads a list of block ids, one per line. Empty lines and lines starting
+/// with `#` are ignored.
+pub(crate) fn load_block_list(path: Option<&Path>) -> Result<HashSet<String>> {
+    let Some(path) = path else {
+        return Ok(PLAYER_BLOCKS.iter().map(|b| b.to_string()).collect());
+    };
+    let text =
+        read_to_string(path).with_context(|| format!("Failed to read `{}`", path.display()))?;
+    Ok(text
+        .lines()
+        .map(str::trim)
+        .filter(|l| !l.is_empty() && !l.starts_with('#'))
+        .map(String::from)
+        .collect())
+}
+
+/// Whether the chunk has block entities a player probably placed, or any of
+/// the given blocks in its palettes.
+pub(crate) fn touched_by_player(chunk: &dyn Chunk, player_blocks: &HashSet<String>) -> bool {
+    for entity in chunk.block_entities() {
+        let Value::Compound(entity) = entity else {
+            continue;
+        };
+        // unopened loot chests in structures
+        if entity.contains_key("LootTable") {
+            continue;
+        }
+        match entity.get("id") {
+            Some(Value::String(id)) if NATURAL_BLOCK_ENTITIES.contains(&id.as_str()) => {}
+            _ => return true,
+        }
+    }
+
+    chunk.sections().iter().any(|section| {
+        let Some(ref block_states) = section.block_states else {
+            return false;
+        };
+        block_states
+            .palette()
+            .iter()
+            .any(|block| player_blocks.contains(block.name()))
+    })
+}
+
+/// Finds the chunks that look like players built something in them
+pub(crate) fn read_player_chunks(
+    region_files: &[PathBuf],
+    player_blocks: &HashSet<String>,
+) -> Result<HashSet<(i32, i32)>> {
+    log::info!("Looking for player made blocks...");
+    let chunks = region_files
+        .par_iter()
+        .map(|path: &PathBuf| -> Result<HashSet<(i32, i32)>> {
+            let mut chunks = HashSet::new();
+            let file = File::options().read(true).write(false).open(path)?;
+            let mut reg = Region::from_stream(file)?;
+
+            let (reg_x, reg_z) = region_coords(path)?;
+
+            for raw_chunk in reg.iter() {
+                let raw_chunk = raw_chunk?;
+                let x = reg_x * 32 + raw_chunk.x as i32;
+                let z = reg_z * 32 + raw_chunk.z as i32;
+
+                let chunk = match load_chunk(raw_chunk.data.as_slice()) {
+                    Ok(c) => c,
+                    Err(e) => {
+                        log::debug!("reading chunk {x} {z}: {:?}", e);
+                        continue;
+                    }
+                };
+
+                if touched_by_player(chunk.as_ref(), player_blocks) {
+                    chunks.insert((x, z));
+                }
+            }
+            Ok(chunks)
+        })
+        .filter_map(|x| x.ok())
+        .reduce(HashSet::new, |mut a, b| {
+            a.extend(b);
+            a
+        });
+    log::info!("{} chunks with player made blocks.", chunks.len());
+    Ok(chunks)
+}
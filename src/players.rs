@@ -0,0 +1,232 @@
+use crate::cli::PlayersArgs;
+use crate::dat::read_dat;
+use crate::entities::entity_pos;
+use crate::items::item_count;
+use crate::nbt::{get_int, get_string, Compound};
+
+use anyhow::{bail, Result};
+use fastnbt::Value;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs::{read_dir, File};
+use std::path::{Path, PathBuf};
+
+#[derive(Deserialize, Debug)]
+struct UserCacheEntry {
+    name: String,
+    uuid: String,
+}
+
+/// Player names by UUID, from the `usercache.json` next to the world (on
+/// servers) or inside it
+fn read_usercache(world: &Path) -> HashMap<String, String> {
+    let candidates = [
+        world.parent().map(|p| p.join("usercache.json")),
+        Some(world.join("usercache.json")),
+    ];
+    for path in candidates.into_iter().flatten() {
+        let Ok(file) = File::open(&path) else {
+            continue;
+        };
+        match serde_json::from_reader::<_, Vec<UserCacheEntry>>(file) {
+            Ok(entries) => {
+                return entries
+                    .into_iter()
+                    .map(|e| (e.uuid.to_lowercase(), e.name))
+                    .collect()
+            }
+            Err(e) => log::debug!("error reading {}: {}", path.display(), e),
+        }
+    }
+    HashMap::new()
+}
+
+#[derive(Serialize, Debug)]
+struct InventoryItem {
+    slot: Option<i32>,
+    id: String,
+    count: i64,
+}
+
+#[derive(Serialize, Debug)]
+struct Spawn {
+    dimension: Option<String>,
+    x: i32,
+    y: i32,
+    z: i32,
+}
+
+#[derive(Serialize, Debug)]
+struct Player {
+    uuid: String,
+    name: Option<String>,
+    dimension: Option<String>,
+    pos: Option<(f64, f64, f64)>,
+    spawn: Option<Spawn>,
+    game_mode: Option<i32>,
+    health: Option<f32>,
+    xp_level: Option<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    inventory: Option<Vec<InventoryItem>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    ender_chest: Option<Vec<InventoryItem>>,
+}
+
+/// Dimensions were stored as numbers before 1.16
+fn dimension(value: Option<&Value>) -> Option<String> {
+    match value? {
+        Value::String(s) => Some(s.clone()),
+        Value::Int(0) => Some("minecraft:overworld".to_string()),
+        Value::Int(-1) => Some("minecraft:the_nether".to_string()),
+        Value::Int(1) => Some("minecraft:the_end".to_string()),
+        _ => None,
+    }
+}
+
+fn spawn(data: &Compound) -> Option<Spawn> {
+    // 1.21.5 moved the spawn point into a `respawn` compound
+    if let Some(Value::Compound(respawn)) = data.get("respawn") {
+        let Some(Value::IntArray(pos)) = respawn.get("pos") else {
+            return None;
+        };
+        let [x, y, z] = pos[..] else {
+            return None;
+        };
+        return Some(Spawn {
+            dimension: dimension(respawn.get("dimension")),
+            x,
+            y,
+            z,
+        });
+    }
+    Some(Spawn {
+        dimension: dimension(data.get("SpawnDimension")),
+        x: get_int(data, "SpawnX")?,
+        y: get_int(data, "SpawnY")?,
+        z: get_int(data, "SpawnZ")?,
+    })
+}
+
+fn inventory(data: &Compound, key: &str) -> Vec<InventoryItem> {
+    let Some(Value::List(items)) = data.get(key) else {
+        return vec![];
+    };
+    items
+        .iter()
+        .filter_map(|item| {
+            let Value::Compound(item) = item else {
+                return None;
+            };
+            Some(InventoryItem {
+                slot: get_int(item, "Slot"),
+                id: get_string(item, "id")?,
+                count: item_count(item),
+            })
+        })
+        .collect()
+}
+
+fn read_player(path: &Path, names: &HashMap<String, String>, items: bool) -> Result<Player> {
+    let data = read_dat(path)?;
+    let uuid = path
+        .file_stem()
+        .unwrap_or_default()
+        .to_string_lossy()
+        .to_lowercase();
+    Ok(Player {
+        name: names.get(&uuid).cloned(),
+        uuid,
+        dimension: dimension(data.get("Dimension")),
+        pos: entity_pos(&data),
+        spawn: spawn(&data),
+        game_mode: get_int(&data, "playerGameType"),
+        health: match data.get("Health") {
+            Some(Value::Float(h)) => Some(*h),
+            _ => None,
+        },
+        xp_level: get_int(&data, "XpLevel"),
+        inventory: items.then(|| inventory(&data, "Inventory")),
+        ender_chest: items.then(|| inventory(&data, "EnderItems")),
+    })
+}
+
+fn print_player(player: &Player) {
+    let name = player.name.as_deref().unwrap_or("?");
+    let dimension = player.dimension.as_deref().unwrap_or("?");
+    match player.pos {
+        Some((x, y, z)) => println!(
+            "{} {name} {dimension} {:.1} {:.1} {:.1}",
+            player.uuid, x, y, z
+        ),
+        None => println!("{} {name} {dimension}", player.uuid),
+    }
+    if let Some(ref spawn) = player.spawn {
+        println!(
+            "  spawn: {} {} {} {}",
+            spawn.dimension.as_deref().unwrap_or("?"),
+            spawn.x,
+            spawn.y,
+            spawn.z
+        );
+    }
+    for (title, items) in [
+        ("inventory", &player.inventory),
+        ("ender chest", &player.ender_chest),
+    ] {
+        let Some(items) = items else {
+            continue;
+        };
+        println!("  {title}:");
+        for item in items {
+            let slot = item.slot.map(|s| s.to_string()).unwrap_or_default();
+            println!("    {slot:>4} {} {}", item.id, item.count);
+        }
+    }
+}
+
+pub(crate) fn players(args: &PlayersArgs) -> Result<()> {
+    let names = read_usercache(&args.world);
+
+    let mut files: Vec<PathBuf> = read_dir(args.world.join("playerdata"))?
+        .filter_map(|entry| entry.ok().map(|e| e.path()))
+        .filter(|path| path.extension().is_some_and(|ext| ext == "dat"))
+        .collect();
+    files.sort();
+
+    if let Some(ref wanted) = args.player {
+        let wanted = wanted.to_lowercase();
+        let uuid = names
+            .iter()
+            .find(|(_, name)| name.to_lowercase() == wanted)
+            .map(|(uuid, _)| uuid.clone())
+            .unwrap_or(wanted);
+        files.retain(|path| {
+            path.file_stem()
+                .is_some_and(|stem| stem.to_string_lossy().to_lowercase() == uuid)
+        });
+        if files.is_empty() {
+            bail!(
+                "player {} not found",
+                args.player.as_deref().unwrap_or_default()
+            );
+        }
+    }
+
+    // a single player is shown in detail
+    let items = args.items || args.player.is_some();
+    for path in files {
+        let player = match read_player(&path, &names, items) {
+            Ok(p) => p,
+            Err(e) => {
+                log::debug!("error reading {}: {}", path.display(), e);
+                continue;
+            }
+        };
+        if args.json {
+            println!("{}", serde_json::to_string(&player)?);
+        } else {
+            print_player(&player);
+        }
+    }
+    Ok(())
+}
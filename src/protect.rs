@@ -0,0 +1,167 @@
+use crate::area::Shape;
+use crate::cli::{parse_coords, parse_point};
+
+use anyhow::{bail, Context, Result};
+use std::collections::HashSet;
+use std::fs::read_to_string;
+use std::path::Path;
+
+/// Reads the chunks listed in a protect file.
+///
+/// Each line is either `chunk <x>,<z>` with chunk coordinates,
+/// `box <x>,<y>,<z> <x>,<y>,<z>` with the block coordinates of two opposite
+/// corners, `circle <x>,<z> <radius>` or `polygon <x>,<z> <x>,<z> ...` in
+/// block coordinates. A `[name]` line starts a named area made of the
+/// entries following it. Empty lines and lines starting with `#` are
+/// ignored.
+pub(crate) fn load_protected(path: &Path) -> Result<HashSet<(i32, i32)>> {
+    let text =
+        read_to_string(path).with_context(|| format!("Failed to read `{}`", path.display()))?;
+    parse_protected(&text).with_context(|| format!("Failed to parse `{}`", path.display()))
+}
+
+/// Adds the chunks of `shape` between chunk corners `from` and `to`
+fn protect_shape(
+    protected: &mut HashSet<(i32, i32)>,
+    shape: &Shape,
+    from: (i32, i32),
+    to: (i32, i32),
+) -> usize {
+    let mut count = 0;
+    for x in from.0..=to.0 {
+        for z in from.1..=to.1 {
+            if shape.contains_chunk(x, z) {
+                protected.insert((x, z));
+                count += 1;
+            }
+        }
+    }
+    count
+}
+
+fn parse_protected(text: &str) -> Result<HashSet<(i32, i32)>> {
+    let mut protected = HashSet::new();
+    let mut area = String::from("(unnamed)");
+    let mut area_chunks = 0;
+
+    for (n, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        if let Some(name) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+            log::debug!("{area_chunks} chunks protected by {area}");
+            area = name.trim().to_string();
+            area_chunks = 0;
+            continue;
+        }
+
+        let parts: Vec<&str> = line.split_whitespace().collect();
+        match parts.as_slice() {
+            ["chunk", point] => {
+                let chunk = parse_point(point).with_context(|| format!("line {}", n + 1))?;
+                protected.insert(chunk);
+                area_chunks += 1;
+            }
+            ["box", from, to] => {
+                let from = parse_coords(from).with_context(|| format!("line {}", n + 1))?;
+                let to = parse_coords(to).with_context(|| format!("line {}", n + 1))?;
+                let (x0, x1) = (from.0.min(to.0) >> 4, from.0.max(to.0) >> 4);
+                let (z0, z1) = (from.2.min(to.2) >> 4, from.2.max(to.2) >> 4);
+                for x in x0..=x1 {
+                    for z in z0..=z1 {
+                        protected.insert((x, z));
+                        area_chunks += 1;
+                    }
+                }
+            }
+            ["circle", center, radius] => {
+                let center = parse_point(center).with_context(|| format!("line {}", n + 1))?;
+                let radius: f64 = radius
+                    .parse()
+                    .with_context(|| format!("line {}: invalid radius", n + 1))?;
+                let r = radius.ceil() as i32;
+                area_chunks += protect_shape(
+                    &mut protected,
+                    &Shape::Circle { center, radius },
+                    ((center.0 - r) >> 4, (center.1 - r) >> 4),
+                    ((center.0 + r) >> 4, (center.1 + r) >> 4),
+                );
+            }
+            ["polygon", points @ ..] if points.len() >= 3 => {
+                let points = points
+                    .iter()
+                    .map(|p| parse_point(p).with_context(|| format!("line {}", n + 1)))
+                    .collect::<Result<Vec<_>>>()?;
+                let from = points.iter().fold((i32::MAX, i32::MAX), |f, p| {
+                    (f.0.min(p.0 >> 4), f.1.min(p.1 >> 4))
+                });
+                let to = points.iter().fold((i32::MIN, i32::MIN), |t, p| {
+                    (t.0.max(p.0 >> 4), t.1.max(p.1 >> 4))
+                });
+                let points = points.iter().map(|p| (p.0 as f64, p.1 as f64)).collect();
+                area_chunks +=
+                    protect_shape(&mut protected, &Shape::Polygon(points), from, to);
+            }
+            _ => bail!(
+                "line {}: expected `chunk x,z`, `box x,y,z x,y,z`, `circle x,z r` or `polygon x,z x,z x,z...`",
+                n + 1
+            ),
+        }
+    }
+    log::debug!("{area_chunks} chunks protected by {area}");
+    log::info!("{} chunks protected.", protected.len());
+
+    Ok(protected)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn protect_file() {
+        let text = "
+            # spawn
+            [spawn]
+            box -1,0,-1 16,64,0
+            [portal]
+            chunk 100,-3
+        ";
+        let protected = parse_protected(text).unwrap();
+        let mut protected: Vec<_> = protected.into_iter().collect();
+        protected.sort();
+        assert_eq!(
+            protected,
+            vec![
+                (-1, -1),
+                (-1, 0),
+                (0, -1),
+                (0, 0),
+                (1, -1),
+                (1, 0),
+                (100, -3)
+            ]
+        );
+        assert!(parse_protected("circle 0,0").is_err());
+        assert!(parse_protected("polygon 0,0 1,1").is_err());
+
+        let protected = parse_protected(
+            "
+            [farm]
+            circle 8,8 20
+            [base]
+            polygon 0,100 40,100 40,140
+            ",
+        )
+        .unwrap();
+        // the circle reaches the chunks around its center, not the corners
+        assert!(protected.contains(&(0, 0)));
+        assert!(protected.contains(&(-1, 0)));
+        assert!(protected.contains(&(1, 1)));
+        assert!(!protected.contains(&(-2, -2)));
+        // chunks are in the triangle when their center is
+        assert!(protected.contains(&(1, 6)));
+        assert!(!protected.contains(&(0, 8)));
+    }
+}
@@ -1,10 +1,18 @@
-use crate::cli::Dimension;
+use crate::area::Area;
+use crate::backup::Backup;
+use crate::cli::PruneArgs;
+use crate::compact::compact_files;
 use crate::nbt::load_chunk;
+use crate::player::{load_block_list, read_player_chunks};
+use crate::protect::load_protected;
+use crate::region::{dimension_dir, region_coords};
+use crate::render::write_chunk_map;
 
-use anyhow::{bail, Context, Result};
-use fastanvil::Region;
+use anyhow::{bail, Result};
+use fastanvil::{Region, Rgba};
 use kiddo::{distance::squared_euclidean, float::kdtree::KdTree};
 use rayon::prelude::*;
+use serde::Serialize;
 use std::collections::{HashMap, HashSet};
 use std::path::{Path, PathBuf};
 use std::{
@@ -14,6 +22,15 @@ use std::{
 
 pub(crate) type ChunkAges = HashMap<(i32, i32), u64>;
 
+/// Pixels per chunk in the `--preview` map
+const PREVIEW_CELL: u32 = 4;
+
+// Colors of the `--preview` map
+const KEPT_OLD: Rgba = [46, 160, 67, 255];
+const KEPT_BUFFER: Rgba = [235, 190, 40, 255];
+const KEPT_PROTECTED: Rgba = [60, 120, 220, 255];
+const PRUNED: Rgba = [200, 50, 50, 255];
+
 pub(crate) fn list_region_files(region_dir: &PathBuf) -> Result<Vec<PathBuf>, anyhow::Error> {
     if !region_dir.is_dir() {
         bail!("{} is not a directory", region_dir.display());
@@ -47,13 +64,7 @@ pub(crate) fn read_inhabited_time(
             let file = File::options().read(true).write(false).open(path)?;
             let mut reg = Region::from_stream(file)?;
 
-            let stem = path.file_stem().context("reading file stem")?;
-
-            let stem = stem.to_string_lossy();
-
-            let mut parts = stem.split('.').skip(1);
-            let reg_x: i32 = parts.next().context("parsing filename")?.parse()?;
-            let reg_z: i32 = parts.next().context("parsing filename")?.parse()?;
+            let (reg_x, reg_z) = region_coords(path)?;
 
             for raw_chunk in reg.iter() {
                 let raw_chunk = raw_chunk?;
@@ -85,13 +96,14 @@ pub(crate) fn read_inhabited_time(
 pub(crate) fn compute_boundary(
     chunk_ages: &HashMap<(i32, i32), u64>,
     inhabited_under: u64,
+    protected: &HashSet<(i32, i32)>,
 ) -> Result<Vec<[f64; 2]>> {
     log::info!("Computing boundary...");
     let old = |x: i32, z: i32| -> bool {
         let Some(t) = chunk_ages.get(&(x, z)) else {
             return false;
         };
-        *t >= inhabited_under
+        *t >= inhabited_under || protected.contains(&(x, z))
     };
     let mut boundary = vec![];
     for (x, z) in chunk_ages.keys() {
@@ -117,67 +129,143 @@ pub(crate) fn compute_boundary(
     Ok(boundary)
 }
 
-pub(crate) fn remove_chunks(
-    mut reg: Region<File>,
+/// What pruning does (or would do) to a single region file.
+#[derive(Serialize, Debug)]
+pub(crate) struct RegionReport {
+    pub(crate) file: PathBuf,
+    pub(crate) x: i32,
+    pub(crate) z: i32,
+    pub(crate) removed: Vec<(i32, i32)>,
+    pub(crate) kept: Vec<(i32, i32)>,
+}
+
+/// Splits the chunks of a region file into the ones `should_remove` accepts
+/// and the ones that stay.
+pub(crate) fn plan_region(
+    reg: &mut Region<File>,
+    path: &Path,
     reg_x: i32,
     reg_z: i32,
-    chunks_kept: &HashSet<(i32, i32)>,
-) -> Result<usize> {
-    let mut pruned = vec![];
+    should_remove: &(dyn Fn(i32, i32, &[u8]) -> bool + Sync),
+) -> Result<RegionReport> {
+    let mut report = RegionReport {
+        file: path.to_path_buf(),
+        x: reg_x,
+        z: reg_z,
+        removed: vec![],
+        kept: vec![],
+    };
 
     for raw_chunk in reg.iter() {
         let raw_chunk = raw_chunk?;
         let x = reg_x * 32 + raw_chunk.x as i32;
         let z = reg_z * 32 + raw_chunk.z as i32;
 
-        let r = load_chunk(raw_chunk.data.as_slice());
-        if r.is_err() {
-            // log::debug!(
-            //     "error reading chunk {x} {z}: {:?}; not pruning it",
-            //     r.unwrap_err()
-            // );
-            continue;
-        };
-
-        if !chunks_kept.contains(&(x, z)) {
-            pruned.push((raw_chunk.x, raw_chunk.z));
+        if should_remove(x, z, raw_chunk.data.as_slice()) {
+            report.removed.push((x, z));
+        } else {
+            report.kept.push((x, z));
         }
     }
+    report.kept.sort();
+    report.removed.sort();
 
-    if pruned.is_empty() {
+    Ok(report)
+}
+
+pub(crate) fn remove_chunks(mut reg: Region<File>, report: &RegionReport) -> Result<usize> {
+    if report.removed.is_empty() {
         return Ok(0);
     }
 
     log::debug!(
         "Removing {} chunks from region ({},{})...",
-        pruned.len(),
-        reg_x,
-        reg_z
+        report.removed.len(),
+        report.x,
+        report.z
     );
-    for (x, z) in pruned.iter() {
-        reg.remove_chunk(*x, *z)?;
+    for (x, z) in report.removed.iter() {
+        reg.remove_chunk(x.rem_euclid(32) as usize, z.rem_euclid(32) as usize)?;
     }
     let mut file = reg.into_inner()?;
     let len = file.stream_position()?;
     file.set_len(len)?;
 
-    Ok(pruned.len())
+    Ok(report.removed.len())
+}
+
+fn format_coords(coords: &[(i32, i32)]) -> String {
+    coords
+        .iter()
+        .map(|(x, z)| format!("{x},{z}"))
+        .collect::<Vec<_>>()
+        .join(" ")
 }
 
-pub(crate) fn prune(
-    world: &Path,
-    dimension: Dimension,
+pub(crate) fn print_reports(reports: &[RegionReport], json: bool) -> Result<()> {
+    for report in reports {
+        if json {
+            println!("{}", serde_json::to_string(report)?);
+            continue;
+        }
+        // show the parent directory too, to tell region, entities and poi files apart
+        let dir = report.file.parent().and_then(|p| p.file_name());
+        let name =
+            Path::new(dir.unwrap_or_default()).join(report.file.file_name().unwrap_or_default());
+        let name = name.display();
+        println!(
+            "{name}: {} removed, {} kept",
+            report.removed.len(),
+            report.kept.len()
+        );
+        if !report.removed.is_empty() {
+            println!("  removed: {}", format_coords(&report.removed));
+        }
+        if !report.kept.is_empty() {
+            println!("  kept: {}", format_coords(&report.kept));
+        }
+    }
+    Ok(())
+}
+
+/// Color of each chunk in the `--preview` map, by why it's kept or whether
+/// it's pruned
+fn preview_colors(
+    chunk_ages: &HashMap<(i32, i32), u64>,
+    chunks_kept: &HashSet<(i32, i32)>,
+    protected: &HashSet<(i32, i32)>,
     inhabited_under: u64,
-    buffer: f64,
-) -> Result<()> {
-    let region_dir = match dimension {
-        Dimension::Overworld => world.join("region"),
-        Dimension::Nether => world.join("DIM-1/region"),
-        Dimension::End => world.join("DIM1/region"),
-    };
+) -> HashMap<(i32, i32), Rgba> {
+    chunk_ages
+        .iter()
+        .map(|(&chunk, &t)| {
+            let color = if !chunks_kept.contains(&chunk) {
+                PRUNED
+            } else if protected.contains(&chunk) {
+                KEPT_PROTECTED
+            } else if t >= inhabited_under {
+                KEPT_OLD
+            } else {
+                KEPT_BUFFER
+            };
+            (chunk, color)
+        })
+        .collect()
+}
+
+pub(crate) fn prune(args: &PruneArgs, backup: &Backup) -> Result<()> {
+    let region_dir = dimension_dir(&args.world, &args.dimension).join("region");
     let region_files = list_region_files(&region_dir)?;
     let chunk_ages = read_inhabited_time(&region_files)?;
-    let boundary = compute_boundary(&chunk_ages, inhabited_under)?;
+    let mut protected = match args.protect {
+        Some(ref path) => load_protected(path)?,
+        None => HashSet::new(),
+    };
+    if args.keep_player_made {
+        let player_blocks = load_block_list(args.player_blocks.as_deref())?;
+        protected.extend(read_player_chunks(&region_files, &player_blocks)?);
+    }
+    let boundary = compute_boundary(&chunk_ages, args.inhabited_under, &protected)?;
 
     log::info!("Building KDTree...");
     let boundary_kd: KdTree<f64, usize, 2, 256, u32> = (&boundary).into();
@@ -186,43 +274,131 @@ pub(crate) fn prune(
 
     log::info!("Creating buffer zone...");
     let mut chunks_kept = HashSet::new();
-    for ((x, z), t) in chunk_ages {
-        if t >= inhabited_under {
+    for (&(x, z), &t) in chunk_ages.iter() {
+        if t >= args.inhabited_under || protected.contains(&(x, z)) {
             chunks_kept.insert((x, z));
             continue;
         }
         let point = [x as f64, z as f64];
         let (distance, _) = boundary_kd.nearest_one(&point, &squared_euclidean);
-        if distance <= buffer.powi(2) {
+        if distance <= args.buffer.powi(2) {
             chunks_kept.insert((x, z));
         }
     }
+    if let Some(area) = Area::from_args(args)? {
+        chunks_kept.retain(|(x, z)| protected.contains(&(*x, *z)) || area.keeps(*x, *z));
+    }
     log::info!("{} chunks will be kept.", chunks_kept.len());
 
-    log::info!("Pruning...");
-    let pruned: Result<Vec<usize>> = region_files
-        .par_iter()
-        .map(|path: &PathBuf| -> Result<usize> {
-            // let mut chunk_age: ChunkAges = HashMap::new();
-            let file = File::options().read(true).write(true).open(path)?;
+    if let Some(ref path) = args.preview {
+        let colors = preview_colors(&chunk_ages, &chunks_kept, &protected, args.inhabited_under);
+        write_chunk_map(path, &colors, PREVIEW_CELL)?;
+        log::info!("Preview written to {}", path.display());
+    }
 
-            let Ok(reg) = Region::from_stream(file) else {
-                // if reading the chunk fails, we count 0 chunks pruned and continue
-                return Ok(0);
-            };
+    if args.dry_run {
+        log::info!("Dry run, no region files will be modified.");
+    } else {
+        log::info!("Pruning...");
+    }
+    let verb = if args.dry_run {
+        "would be pruned"
+    } else {
+        "pruned"
+    };
+    let mut reports = prune_dir(&region_files, args.dry_run, backup, &|x, z, data| {
+        // chunks we can't read are never pruned
+        load_chunk(data).is_ok() && !chunks_kept.contains(&(x, z))
+    })?;
+    let pruned: HashSet<(i32, i32)> = reports
+        .iter()
+        .flat_map(|r| r.removed.iter().copied())
+        .collect();
+    log::info!("{} chunks {verb}.", pruned.len());
+
+    // Since 1.17 entities and points of interest are stored in their own
+    // region files. Remove the entries belonging to the pruned chunks so
+    // they don't linger around.
+    for kind in ["entities", "poi"] {
+        let dir = dimension_dir(&args.world, &args.dimension).join(kind);
+        if !dir.is_dir() {
+            log::debug!("{} doesn't exist, skipping", dir.display());
+            continue;
+        }
+        let files = list_region_files(&dir)?;
+        let kind_reports = prune_dir(&files, args.dry_run, backup, &|x, z, _| {
+            pruned.contains(&(x, z))
+        })?;
+        log::info!(
+            "{} {kind} chunks {verb}.",
+            kind_reports.iter().map(|r| r.removed.len()).sum::<usize>()
+        );
+        reports.extend(kind_reports);
+    }
 
-            let stem = path.file_stem().context("reading file stem")?;
-            let stem = stem.to_string_lossy();
+    if args.dry_run {
+        print_reports(&reports, args.json)?;
+    }
+
+    if args.compact {
+        let files: Vec<PathBuf> = reports
+            .iter()
+            .filter(|r| !r.removed.is_empty())
+            .map(|r| r.file.clone())
+            .collect();
+        compact_files(&files, false, backup)?;
+    }
+
+    Ok(())
+}
+
+fn prune_dir(
+    region_files: &[PathBuf],
+    dry_run: bool,
+    backup: &Backup,
+    should_remove: &(dyn Fn(i32, i32, &[u8]) -> bool + Sync),
+) -> Result<Vec<RegionReport>> {
+    let reports: Result<Vec<Option<RegionReport>>> = region_files
+        .par_iter()
+        .map(|path: &PathBuf| -> Result<Option<RegionReport>> {
+            let file = File::options().read(true).write(!dry_run).open(path)?;
 
-            let mut parts = stem.split('.').skip(1);
-            let reg_x: i32 = parts.next().context("parsing filename")?.parse()?;
-            let reg_z: i32 = parts.next().context("parsing filename")?.parse()?;
+            let Ok(mut reg) = Region::from_stream(file) else {
+                // if reading the region fails, we count 0 chunks pruned and continue
+                return Ok(None);
+            };
 
-            remove_chunks(reg, reg_x, reg_z, &chunks_kept)
+            let (reg_x, reg_z) = region_coords(path)?;
+            let report = plan_region(&mut reg, path, reg_x, reg_z, should_remove)?;
+            if !dry_run && !report.removed.is_empty() {
+                backup.save(path)?;
+                let removed = remove_chunks(reg, &report)?;
+                backup.record(path, format!("removed {removed} chunks"))?;
+            }
+            Ok(Some(report))
         })
         .collect();
+    Ok(reports?.into_iter().flatten().collect())
+}
 
-    log::info!("{} chunks pruned.", pruned?.iter().sum::<usize>());
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-    Ok(())
+    #[test]
+    fn preview_classification() {
+        let chunk_ages = HashMap::from([((0, 0), 500), ((1, 0), 10), ((2, 0), 10), ((3, 0), 10)]);
+        let chunks_kept = HashSet::from([(0, 0), (1, 0), (2, 0)]);
+        let protected = HashSet::from([(2, 0)]);
+        let colors = preview_colors(&chunk_ages, &chunks_kept, &protected, 100);
+        assert_eq!(
+            colors,
+            HashMap::from([
+                ((0, 0), KEPT_OLD),
+                ((1, 0), KEPT_BUFFER),
+                ((2, 0), KEPT_PROTECTED),
+                ((3, 0), PRUNED),
+            ])
+        );
+    }
 }
@@ -0,0 +1,420 @@
+use crate::backup::Backup;
+use crate::cli::{ChunkArgs, Coords, Dimension};
+use crate::nbt::{get_int, Compound};
+use crate::prune::list_region_files;
+use crate::rect_intersects_bounds;
+
+use anyhow::{bail, Context, Result};
+use clap::ValueEnum;
+use fastanvil::Region;
+use fastnbt::{from_bytes, to_bytes, Value};
+use flate2::read::{GzDecoder, ZlibDecoder};
+use rayon::prelude::*;
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+/// Region files are allocated in sectors of this many bytes
+pub(crate) const SECTOR_SIZE: u64 = 4096;
+
+/// A slot in use in the header of a region file, as stored, without any
+/// checking: it may point anywhere.
+#[derive(Debug, Clone)]
+pub(crate) struct ChunkLocation {
+    /// position of the chunk within the region, 0 to 31
+    pub(crate) x: usize,
+    pub(crate) z: usize,
+    /// first sector of the chunk, counting the two header sectors
+    pub(crate) sector: u64,
+    pub(crate) sectors: u64,
+    /// seconds since the epoch of the last time the chunk was saved
+    pub(crate) timestamp: u32,
+}
+
+/// Reads the location and timestamp tables of a region file directly, so
+/// that broken files can be inspected instead of rejected as a whole.
+pub(crate) fn read_header(file: &mut (impl Read + Seek)) -> Result<Vec<ChunkLocation>> {
+    let mut header = vec![0u8; 2 * SECTOR_SIZE as usize];
+    file.seek(SeekFrom::Start(0))?;
+    file.read_exact(&mut header)
+        .context("file is shorter than a region header")?;
+
+    let mut locations = vec![];
+    for i in 0..1024 {
+        let l = &header[i * 4..i * 4 + 4];
+        let t = &header[4096 + i * 4..4096 + i * 4 + 4];
+        let sector = u32::from_be_bytes([0, l[0], l[1], l[2]]) as u64;
+        let sectors = l[3] as u64;
+        if sector == 0 && sectors == 0 {
+            continue;
+        }
+        locations.push(ChunkLocation {
+            x: i % 32,
+            z: i / 32,
+            sector,
+            sectors,
+            timestamp: u32::from_be_bytes([t[0], t[1], t[2], t[3]]),
+        });
+    }
+    Ok(locations)
+}
+
+/// Reads the compression type and the still compressed data of a chunk
+pub(crate) fn read_chunk_payload(
+    file: &mut (impl Read + Seek),
+    location: &ChunkLocation,
+) -> Result<(u8, Vec<u8>)> {
+    file.seek(SeekFrom::Start(location.sector * SECTOR_SIZE))?;
+    let mut head = [0u8; 5];
+    file.read_exact(&mut head)?;
+    let len = u32::from_be_bytes([head[0], head[1], head[2], head[3]]) as u64;
+    if len == 0 || len + 4 > location.sectors * SECTOR_SIZE {
+        bail!(
+            "length {len} doesn't fit in its {} sectors",
+            location.sectors
+        );
+    }
+    let mut data = vec![0u8; len as usize - 1];
+    file.read_exact(&mut data)?;
+    Ok((head[4], data))
+}
+
+/// A chunk as stored in a region file, still compressed
+#[derive(Debug)]
+pub(crate) struct StoredChunk {
+    pub(crate) x: usize,
+    pub(crate) z: usize,
+    pub(crate) timestamp: u32,
+    pub(crate) compression: u8,
+    pub(crate) data: Vec<u8>,
+}
+
+impl StoredChunk {
+    /// Sectors taken by the chunk, with its length and compression type
+    pub(crate) fn sectors(&self) -> u64 {
+        (self.data.len() as u64 + 5).div_ceil(SECTOR_SIZE)
+    }
+}
+
+/// Where the chunk stored in a slot says it is: `xPos`/`zPos` in terrain
+/// chunks, `Position` in entity chunks. POI chunks don't say.
+pub(crate) fn stored_coords(chunk: &Compound) -> Option<(i32, i32)> {
+    if let (Some(x), Some(z)) = (get_int(chunk, "xPos"), get_int(chunk, "zPos")) {
+        return Some((x, z));
+    }
+    match chunk.get("Position") {
+        Some(Value::IntArray(pos)) if pos.len() == 2 => Some((pos[0], pos[1])),
+        _ => None,
+    }
+}
+
+/// The chunks of a region file that could be read, and the slots that
+/// couldn't with the reason
+pub(crate) type StoredChunks = (Vec<StoredChunk>, Vec<(ChunkLocation, anyhow::Error)>);
+
+/// Reads every chunk of a region file that can be read, without
+/// decompressing them, along with the slots that couldn't be read and why.
+pub(crate) fn read_stored_chunks(file: &mut (impl Read + Seek)) -> Result<StoredChunks> {
+    let mut chunks = vec![];
+    let mut failed = vec![];
+    for location in read_header(file)? {
+        match read_chunk_payload(file, &location) {
+            Ok((compression, data)) => chunks.push(StoredChunk {
+                x: location.x,
+                z: location.z,
+                timestamp: location.timestamp,
+                compression,
+                data,
+            }),
+            Err(e) => failed.push((location, e)),
+        }
+    }
+    Ok((chunks, failed))
+}
+
+/// Writes a region file holding `chunks` back to back after the header,
+/// leaving no free sectors, and replaces `path` with it once complete.
+/// Returns the size of the new file.
+pub(crate) fn write_region(path: &Path, chunks: &[StoredChunk]) -> Result<u64> {
+    let mut locations = vec![0u8; SECTOR_SIZE as usize];
+    let mut timestamps = vec![0u8; SECTOR_SIZE as usize];
+    let mut sector = 2;
+    for chunk in chunks {
+        let sectors = chunk.sectors();
+        if sectors > 255 {
+            bail!("chunk {} {} is too big for a region file", chunk.x, chunk.z);
+        }
+        let i = (chunk.z * 32 + chunk.x) * 4;
+        locations[i..i + 4].copy_from_slice(&((sector << 8) as u32 | sectors as u32).to_be_bytes());
+        timestamps[i..i + 4].copy_from_slice(&chunk.timestamp.to_be_bytes());
+        sector += sectors;
+    }
+
+    let tmp = path.with_extension("mca.mcl-tmp");
+    {
+        let mut out = BufWriter::new(File::create(&tmp)?);
+        out.write_all(&locations)?;
+        out.write_all(&timestamps)?;
+        for chunk in chunks {
+            let len = chunk.data.len() as u64 + 1;
+            out.write_all(&(len as u32).to_be_bytes())?;
+            out.write_all(&[chunk.compression])?;
+            out.write_all(&chunk.data)?;
+            let padding = (len + 4).next_multiple_of(SECTOR_SIZE) - (len + 4);
+            out.write_all(&vec![0u8; padding as usize])?;
+        }
+        out.into_inner()?.sync_all()?;
+    }
+    fs::rename(&tmp, path).with_context(|| format!("Failed to write `{}`", path.display()))?;
+    Ok(sector * SECTOR_SIZE)
+}
+
+/// Name of the file holding a chunk too big for its region file, which
+/// Minecraft marks by adding 128 to the compression type.
+pub(crate) fn external_chunk_path(region: &Path, x: i32, z: i32) -> PathBuf {
+    region.with_file_name(format!("c.{x}.{z}.mcc"))
+}
+
+pub(crate) fn decompress(compression: u8, data: &[u8]) -> Result<Vec<u8>> {
+    let mut out = vec![];
+    match compression {
+        1 => {
+            GzDecoder::new(data).read_to_end(&mut out)?;
+        }
+        2 => {
+            ZlibDecoder::new(data).read_to_end(&mut out)?;
+        }
+        3 => out.extend_from_slice(data),
+        4 => bail!("LZ4 compressed chunks are not supported"),
+        c => bail!("unknown compression type {c}"),
+    }
+    Ok(out)
+}
+
+/// Directory holding the `region`, `entities` and `poi` folders of a dimension.
+pub(crate) fn dimension_dir(world: &Path, dimension: &Dimension) -> PathBuf {
+    match dimension {
+        Dimension::Overworld => world.to_path_buf(),
+        Dimension::Nether => world.join("DIM-1"),
+        Dimension::End => world.join("DIM1"),
+    }
+}
+
+/// Every region, entities and POI file of a dimension, or of all of them
+/// when `dimension` is `None`.
+pub(crate) fn world_region_files(
+    world: &Path,
+    dimension: Option<&Dimension>,
+) -> Result<Vec<PathBuf>> {
+    let dimensions = match dimension {
+        Some(dimension) => vec![dimension.clone()],
+        None => Dimension::value_variants().to_vec(),
+    };
+
+    let mut files = vec![];
+    for dimension in dimensions.iter() {
+        for dir in ["region", "entities", "poi"] {
+            let dir = dimension_dir(world, dimension).join(dir);
+            if dir.is_dir() {
+                files.extend(list_region_files(&dir)?);
+            }
+        }
+    }
+    Ok(files)
+}
+
+/// Parses the region coordinates out of a `r.<x>.<z>.mca` file name.
+pub(crate) fn region_coords(path: &Path) -> Result<(i32, i32)> {
+    let stem = path.file_stem().context("reading file stem")?;
+    let stem = stem.to_string_lossy();
+
+    let mut parts = stem.split('.').skip(1);
+    let reg_x: i32 = parts.next().context("parsing filename")?.parse()?;
+    let reg_z: i32 = parts.next().context("parsing filename")?.parse()?;
+    Ok((reg_x, reg_z))
+}
+
+/// The region files selected by a single `region` file argument or by
+/// `--world` and `--dimension`.
+pub(crate) fn select_region_files(args: &ChunkArgs) -> Result<Vec<PathBuf>> {
+    match (&args.region, &args.world, &args.dimension) {
+        (Some(region), _, _) => Ok(vec![region.clone()]),
+        (None, Some(world), Some(dimension)) => {
+            list_region_files(&dimension_dir(world, dimension).join("region"))
+        }
+        _ => bail!("either a region file or --world and --dimension are required"),
+    }
+}
+
+/// Calls `f` with the coordinates and compound of every chunk within the
+/// bounds, one region file at a time.
+pub(crate) fn for_each_chunk(
+    region_files: &[PathBuf],
+    from: Option<&Coords>,
+    to: Option<&Coords>,
+    mut f: impl FnMut(i32, i32, &Compound) -> Result<()>,
+) -> Result<()> {
+    for path in region_files {
+        let (reg_x, reg_z) = region_coords(path)?;
+
+        let rf = (reg_x * 512, reg_z * 512);
+        let rt = (reg_x * 512 + 511, reg_z * 512 + 511);
+        if !rect_intersects_bounds(rf, rt, from, to) {
+            log::debug!("region {reg_x} {reg_z} doesn't intersect bounds, skipping");
+            continue;
+        }
+
+        let file = File::open(path)?;
+        let mut reg = match Region::from_stream(file) {
+            Ok(reg) => reg,
+            Err(e) => {
+                log::debug!("error reading region {}: {}", path.display(), e);
+                continue;
+            }
+        };
+
+        for raw_chunk in reg.iter() {
+            let raw_chunk = raw_chunk?;
+            let x = reg_x * 32 + raw_chunk.x as i32;
+            let z = reg_z * 32 + raw_chunk.z as i32;
+
+            let cf = (x * 16, z * 16);
+            let ct = (x * 16 + 15, z * 16 + 15);
+            if !rect_intersects_bounds(cf, ct, from, to) {
+                continue;
+            }
+
+            let chunk: Compound = match from_bytes(raw_chunk.data.as_slice()) {
+                Ok(c) => c,
+                Err(e) => {
+                    log::debug!("error reading chunk {x} {z}: {}", e);
+                    continue;
+                }
+            };
+            f(x, z, &chunk)?;
+        }
+    }
+    Ok(())
+}
+
+/// Opens a region file to write chunks into it, creating the file, and its
+/// directory, when it doesn't exist or is empty.
+pub(crate) fn open_region_for_writing(path: &Path) -> Result<Region<File>> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let is_new = !path.exists() || fs::metadata(path)?.len() == 0;
+    let file = File::options()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(path)?;
+    let reg = if is_new {
+        Region::new(file)?
+    } else {
+        Region::from_stream(file)?
+    };
+    Ok(reg)
+}
+
+/// Finishes writing a region opened with [`open_region_for_writing`]:
+/// truncates the file after its last chunk and, since fastanvil leaves the
+/// timestamp table alone, stamps the slots in `written` with the current time.
+pub(crate) fn close_region(reg: Region<File>, written: &[(usize, usize)]) -> Result<()> {
+    let mut file = reg.into_inner()?;
+    let len = file.stream_position()?;
+    file.set_len(len)?;
+
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)?
+        .as_secs() as u32;
+    for (x, z) in written {
+        file.seek(SeekFrom::Start(SECTOR_SIZE + (z * 32 + x) as u64 * 4))?;
+        file.write_all(&now.to_be_bytes())?;
+    }
+    Ok(())
+}
+
+/// Edit applied to a chunk by [`edit_chunks`]
+pub(crate) type ChunkEdit<'a> =
+    dyn Fn(i32, i32, &mut HashMap<String, Value>) -> Result<bool> + Sync + 'a;
+
+/// Applies `edit` to every chunk within the bounds, processing region files
+/// in parallel. `edit` gets the chunk coordinates and its compound, and
+/// returns whether it changed anything; only changed chunks are written
+/// back, after backing up their region file. Returns the number of chunks
+/// changed.
+pub(crate) fn edit_chunks(
+    region_files: &[PathBuf],
+    from: Option<&Coords>,
+    to: Option<&Coords>,
+    backup: &Backup,
+    action: &str,
+    edit: &ChunkEdit<'_>,
+) -> Result<usize> {
+    let changed: Result<Vec<usize>> = region_files
+        .par_iter()
+        .map(|path: &PathBuf| -> Result<usize> {
+            let (reg_x, reg_z) = region_coords(path)?;
+
+            let rf = (reg_x * 512, reg_z * 512);
+            let rt = (reg_x * 512 + 511, reg_z * 512 + 511);
+            if !rect_intersects_bounds(rf, rt, from, to) {
+                log::debug!("region {reg_x} {reg_z} doesn't intersect bounds, skipping");
+                return Ok(0);
+            }
+
+            let file = File::options().read(true).write(true).open(path)?;
+            let mut reg = match Region::from_stream(file) {
+                Ok(reg) => reg,
+                Err(e) => {
+                    log::debug!("error reading region {}: {}", path.display(), e);
+                    return Ok(0);
+                }
+            };
+
+            let mut new_chunks = vec![];
+            for raw_chunk in reg.iter() {
+                let raw_chunk = raw_chunk?;
+                let x = reg_x * 32 + raw_chunk.x as i32;
+                let z = reg_z * 32 + raw_chunk.z as i32;
+
+                let cf = (x * 16, z * 16);
+                let ct = (x * 16 + 15, z * 16 + 15);
+                if !rect_intersects_bounds(cf, ct, from, to) {
+                    continue;
+                }
+
+                let mut chunk: HashMap<String, Value> = match from_bytes(raw_chunk.data.as_slice())
+                {
+                    Ok(c) => c,
+                    Err(e) => {
+                        log::debug!("error reading chunk {x} {z}: {}", e);
+                        continue;
+                    }
+                };
+                if edit(x, z, &mut chunk)? {
+                    new_chunks.push((raw_chunk.x, raw_chunk.z, chunk));
+                }
+            }
+
+            if new_chunks.is_empty() {
+                return Ok(0);
+            }
+
+            backup.save(path)?;
+            for (x, z, chunk) in new_chunks.iter() {
+                reg.write_chunk(*x, *z, to_bytes(chunk)?.as_slice())?;
+            }
+            let mut file = reg.into_inner()?;
+            let len = file.stream_position()?;
+            file.set_len(len)?;
+            backup.record(path, format!("{action} in {} chunks", new_chunks.len()))?;
+
+            Ok(new_chunks.len())
+        })
+        .collect();
+    Ok(changed?.iter().sum())
+}
@@ -0,0 +1,509 @@
+use crate::cli::{Coords, RenderArgs};
+use crate::prune::{list_region_files, read_inhabited_time};
+use crate::rect_intersects_bounds;
+use crate::region::{dimension_dir, region_coords};
+
+use anyhow::{bail, Context, Result};
+use fastanvil::biome::Biome;
+use fastanvil::{Block, HeightMode, JavaChunk, Palette, Region, Rgba, TopShadeRenderer};
+use image::RgbaImage;
+use rayon::prelude::*;
+use std::collections::{BTreeSet, HashMap};
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// Largest image rendered, in pixels
+const MAX_PIXELS: u64 = 1 << 30;
+
+/// Color of the region borders drawn on chunk maps
+const GRID: Rgba = [40, 40, 40, 255];
+
+/// Color of blocks that are neither in the palette nor guessed from their
+/// name, bright so they stand out
+const UNKNOWN: Rgba = [255, 0, 255, 255];
+
+/// Colors used when there's no palette file, or it lacks a block
+const DEFAULT_COLORS: &[(&str, Rgba)] = &[
+    ("minecraft:air", [0, 0, 0, 0]),
+    ("minecraft:cave_air", [0, 0, 0, 0]),
+    ("minecraft:void_air", [0, 0, 0, 0]),
+    ("minecraft:water", [63, 118, 228, 180]),
+    ("minecraft:lava", [207, 92, 20, 255]),
+    ("minecraft:grass_block", [127, 178, 56, 255]),
+    ("minecraft:short_grass", [127, 178, 56, 255]),
+    ("minecraft:grass", [127, 178, 56, 255]),
+    ("minecraft:tall_grass", [127, 178, 56, 255]),
+    ("minecraft:fern", [104, 150, 48, 255]),
+    ("minecraft:dirt", [134, 96, 67, 255]),
+    ("minecraft:coarse_dirt", [119, 85, 59, 255]),
+    ("minecraft:rooted_dirt", [144, 103, 76, 255]),
+    ("minecraft:podzol", [91, 63, 24, 255]),
+    ("minecraft:mycelium", [111, 98, 101, 255]),
+    ("minecraft:mud", [60, 57, 60, 255]),
+    ("minecraft:dirt_path", [148, 121, 65, 255]),
+    ("minecraft:farmland", [81, 44, 15, 255]),
+    ("minecraft:sand", [219, 207, 163, 255]),
+    ("minecraft:sandstone", [216, 203, 155, 255]),
+    ("minecraft:red_sand", [190, 102, 33, 255]),
+    ("minecraft:gravel", [136, 126, 126, 255]),
+    ("minecraft:clay", [160, 166, 179, 255]),
+    ("minecraft:stone", [125, 125, 125, 255]),
+    ("minecraft:cobblestone", [122, 122, 122, 255]),
+    ("minecraft:granite", [149, 103, 85, 255]),
+    ("minecraft:diorite", [188, 188, 188, 255]),
+    ("minecraft:andesite", [136, 136, 136, 255]),
+    ("minecraft:deepslate", [80, 80, 82, 255]),
+    ("minecraft:tuff", [108, 109, 102, 255]),
+    ("minecraft:calcite", [223, 224, 220, 255]),
+    ("minecraft:bedrock", [85, 85, 85, 255]),
+    ("minecraft:snow", [249, 254, 254, 255]),
+    ("minecraft:snow_block", [249, 254, 254, 255]),
+    ("minecraft:powder_snow", [248, 253, 253, 255]),
+    ("minecraft:ice", [145, 183, 253, 220]),
+    ("minecraft:packed_ice", [141, 180, 250, 255]),
+    ("minecraft:blue_ice", [116, 167, 253, 255]),
+    ("minecraft:netherrack", [97, 38, 38, 255]),
+    ("minecraft:soul_sand", [81, 62, 50, 255]),
+    ("minecraft:soul_soil", [75, 57, 46, 255]),
+    ("minecraft:basalt", [73, 72, 77, 255]),
+    ("minecraft:blackstone", [42, 36, 41, 255]),
+    ("minecraft:crimson_nylium", [130, 31, 31, 255]),
+    ("minecraft:warped_nylium", [43, 114, 101, 255]),
+    ("minecraft:glowstone", [171, 131, 84, 255]),
+    ("minecraft:end_stone", [219, 222, 158, 255]),
+    ("minecraft:obsidian", [15, 10, 24, 255]),
+    ("minecraft:bamboo", [93, 144, 19, 255]),
+    ("minecraft:sugar_cane", [148, 192, 101, 255]),
+    ("minecraft:cactus", [85, 127, 43, 255]),
+    ("minecraft:kelp", [87, 130, 42, 255]),
+    ("minecraft:seagrass", [51, 128, 15, 255]),
+    ("minecraft:lily_pad", [32, 128, 48, 255]),
+    ("minecraft:pumpkin", [198, 118, 24, 255]),
+    ("minecraft:melon", [111, 145, 30, 255]),
+];
+
+/// Colors guessed from the end of the block id, for the many variants of
+/// wood, stone and colored blocks
+const SUFFIX_COLORS: &[(&str, Rgba)] = &[
+    ("_leaves", [60, 110, 30, 255]),
+    ("_log", [102, 81, 51, 255]),
+    ("_wood", [102, 81, 51, 255]),
+    ("_stem", [92, 25, 29, 255]),
+    ("_planks", [162, 130, 78, 255]),
+    ("_slab", [150, 130, 100, 255]),
+    ("_stairs", [150, 130, 100, 255]),
+    ("_fence", [162, 130, 78, 255]),
+    ("_door", [162, 130, 78, 255]),
+    ("_trapdoor", [162, 130, 78, 255]),
+    ("_wool", [220, 220, 220, 255]),
+    ("_carpet", [220, 220, 220, 255]),
+    ("_terracotta", [152, 94, 67, 255]),
+    ("_concrete", [180, 180, 180, 255]),
+    ("_bricks", [150, 97, 83, 255]),
+    ("_ore", [125, 125, 125, 255]),
+    ("_glass", [200, 220, 230, 100]),
+    ("glass_pane", [200, 220, 230, 100]),
+    ("_flower", [200, 60, 60, 255]),
+    ("_tulip", [200, 60, 60, 255]),
+    ("_sapling", [60, 110, 30, 255]),
+    ("_bush", [60, 110, 30, 255]),
+    ("_coral", [200, 80, 120, 255]),
+    ("_rail", [120, 110, 90, 255]),
+    ("_torch", [0, 0, 0, 0]),
+    ("_button", [0, 0, 0, 0]),
+    ("_sign", [0, 0, 0, 0]),
+];
+
+/// Block colors by id, or by id and properties like
+/// `minecraft:oak_log|axis=y`. Remembers the blocks it had to guess.
+struct BlockPalette {
+    colors: HashMap<String, Rgba>,
+    unknown: Mutex<BTreeSet<String>>,
+}
+
+/// Reads a color written as `[r, g, b]`, `[r, g, b, a]` or `"#rrggbb"`
+fn parse_color(value: &serde_json::Value) -> Option<Rgba> {
+    match value {
+        serde_json::Value::Array(parts) if parts.len() == 3 || parts.len() == 4 => {
+            let mut color = [255; 4];
+            for (c, part) in color.iter_mut().zip(parts) {
+                *c = u8::try_from(part.as_u64()?).ok()?;
+            }
+            Some(color)
+        }
+        serde_json::Value::String(hex) => {
+            let hex = hex.strip_prefix('#')?;
+            if hex.len() != 6 && hex.len() != 8 {
+                return None;
+            }
+            let mut color = [255; 4];
+            for (i, c) in color.iter_mut().take(hex.len() / 2).enumerate() {
+                *c = u8::from_str_radix(hex.get(i * 2..i * 2 + 2)?, 16).ok()?;
+            }
+            Some(color)
+        }
+        _ => None,
+    }
+}
+
+impl BlockPalette {
+    /// The default colors, overridden by the ones in a JSON file mapping
+    /// block ids to colors. The `blockstates` object of fastanvil's
+    /// `palette.json` works too.
+    fn load(path: Option<&Path>) -> Result<BlockPalette> {
+        let mut colors: HashMap<String, Rgba> = DEFAULT_COLORS
+            .iter()
+            .map(|(id, color)| (id.to_string(), *color))
+            .collect();
+        if let Some(path) = path {
+            let json: serde_json::Value = serde_json::from_str(&fs::read_to_string(path)?)
+                .with_context(|| format!("reading palette {}", path.display()))?;
+            let entries = json.get("blockstates").unwrap_or(&json);
+            let Some(entries) = entries.as_object() else {
+                bail!("palette {} must be a JSON object", path.display());
+            };
+            for (id, color) in entries {
+                let Some(color) = parse_color(color) else {
+                    bail!("palette color of {id} must be [r, g, b], [r, g, b, a] or \"#rrggbb\"");
+                };
+                colors.insert(id.clone(), color);
+            }
+        }
+        Ok(BlockPalette {
+            colors,
+            unknown: Mutex::new(BTreeSet::new()),
+        })
+    }
+}
+
+impl Palette for BlockPalette {
+    fn pick(&self, block: &Block, _biome: Option<Biome>) -> Rgba {
+        let known = self
+            .colors
+            .get(block.encoded_description())
+            .or_else(|| self.colors.get(block.name()));
+        if let Some(color) = known {
+            return *color;
+        }
+        if let Some((_, color)) = SUFFIX_COLORS
+            .iter()
+            .find(|(suffix, _)| block.name().ends_with(*suffix))
+        {
+            return *color;
+        }
+        if let Ok(mut unknown) = self.unknown.lock() {
+            unknown.insert(block.name().to_string());
+        }
+        UNKNOWN
+    }
+}
+
+/// Color of a heatmap cell, from blue when `t` is 0 to red when it's 1
+pub(crate) fn heat_color(t: f64) -> Rgba {
+    let t = t.clamp(0.0, 1.0);
+    [(255.0 * t) as u8, 0, (255.0 * (1.0 - t)) as u8, 255]
+}
+
+/// Mixes `over` into `under`, `alpha` being how much of `over` shows
+fn blend(under: Rgba, over: Rgba, alpha: f64) -> Rgba {
+    let mix = |a: u8, b: u8| (a as f64 * (1.0 - alpha) + b as f64 * alpha).round() as u8;
+    [
+        mix(under[0], over[0]),
+        mix(under[1], over[1]),
+        mix(under[2], over[2]),
+        under[3].max(over[3]),
+    ]
+}
+
+/// A chunk's coordinates and its 16x16 pixels in ZX order
+type RenderedChunk = ((i32, i32), [Rgba; 256]);
+
+/// Renders the chunks of a region file within the bounds
+fn render_region(
+    path: &Path,
+    palette: &BlockPalette,
+    from: Option<&Coords>,
+    to: Option<&Coords>,
+) -> Result<Vec<RenderedChunk>> {
+    let (reg_x, reg_z) = region_coords(path)?;
+    let mut reg = Region::from_stream(File::open(path)?)?;
+    let mut chunks: HashMap<(usize, usize), JavaChunk> = HashMap::new();
+    for raw_chunk in reg.iter() {
+        let raw_chunk = raw_chunk?;
+        match JavaChunk::from_bytes(&raw_chunk.data) {
+            Ok(chunk) => {
+                chunks.insert((raw_chunk.x, raw_chunk.z), chunk);
+            }
+            Err(e) => log::debug!(
+                "error reading chunk {} {} of {}: {e}",
+                raw_chunk.x,
+                raw_chunk.z,
+                path.display()
+            ),
+        }
+    }
+
+    let renderer = TopShadeRenderer::new(palette, HeightMode::Calculate);
+    let mut rendered = vec![];
+    for (&(rx, rz), chunk) in chunks.iter() {
+        let x = reg_x * 32 + rx as i32;
+        let z = reg_z * 32 + rz as i32;
+        if !rect_intersects_bounds((x * 16, z * 16), (x * 16 + 15, z * 16 + 15), from, to) {
+            continue;
+        }
+        // the chunk to the north shades the top row; the one in the next
+        // region up is left out, like fastanvil does
+        let north = rz.checked_sub(1).and_then(|nz| chunks.get(&(rx, nz)));
+        rendered.push(((x, z), renderer.render(chunk, north)));
+    }
+    Ok(rendered)
+}
+
+/// Blocks covered by the image: the rendered chunks, cut to the bounds
+fn image_extent(
+    chunks: &[RenderedChunk],
+    from: Option<&Coords>,
+    to: Option<&Coords>,
+) -> Option<((i32, i32), (i32, i32))> {
+    let min_x = chunks.iter().map(|((x, _), _)| x * 16).min()?;
+    let min_z = chunks.iter().map(|((_, z), _)| z * 16).min()?;
+    let max_x = chunks.iter().map(|((x, _), _)| x * 16 + 15).max()?;
+    let max_z = chunks.iter().map(|((_, z), _)| z * 16 + 15).max()?;
+    let (mut f, mut t) = ((min_x, min_z), (max_x, max_z));
+    match (from, to) {
+        (Some(from), Some(to)) => {
+            f = (f.0.max(from.0.min(to.0)), f.1.max(from.2.min(to.2)));
+            t = (t.0.min(from.0.max(to.0)), t.1.min(from.2.max(to.2)));
+        }
+        (Some(from), None) => f = (f.0.max(from.0), f.1.max(from.2)),
+        (None, Some(to)) => t = (t.0.min(to.0), t.1.min(to.2)),
+        (None, None) => {}
+    }
+    Some((f, t))
+}
+
+/// Writes a PNG with a square of `cell` pixels per chunk, in the given
+/// colors, and lines along region borders
+pub(crate) fn write_chunk_map(
+    path: &Path,
+    chunks: &HashMap<(i32, i32), Rgba>,
+    cell: u32,
+) -> Result<()> {
+    let Some(min_x) = chunks.keys().map(|(x, _)| *x).min() else {
+        bail!("there are no chunks to draw");
+    };
+    let min_z = chunks.keys().map(|(_, z)| *z).min().unwrap_or(0);
+    let max_x = chunks.keys().map(|(x, _)| *x).max().unwrap_or(0);
+    let max_z = chunks.keys().map(|(_, z)| *z).max().unwrap_or(0);
+
+    let width = (max_x - min_x + 1) as u64 * cell as u64;
+    let height = (max_z - min_z + 1) as u64 * cell as u64;
+    if width * height > MAX_PIXELS {
+        bail!("a {width}x{height} image is too big");
+    }
+    let mut image = RgbaImage::new(width as u32, height as u32);
+    for (&(x, z), color) in chunks.iter() {
+        let px = (x - min_x) as u32 * cell;
+        let pz = (z - min_z) as u32 * cell;
+        for dz in 0..cell {
+            for dx in 0..cell {
+                image.put_pixel(px + dx, pz + dz, image::Rgba(*color));
+            }
+        }
+    }
+    // the first row and column of pixels of every region
+    for x in min_x..=max_x {
+        if x.rem_euclid(32) == 0 {
+            let px = (x - min_x) as u32 * cell;
+            for pz in 0..height as u32 {
+                image.put_pixel(px, pz, image::Rgba(GRID));
+            }
+        }
+    }
+    for z in min_z..=max_z {
+        if z.rem_euclid(32) == 0 {
+            let pz = (z - min_z) as u32 * cell;
+            for px in 0..width as u32 {
+                image.put_pixel(px, pz, image::Rgba(GRID));
+            }
+        }
+    }
+
+    image
+        .save(path)
+        .with_context(|| format!("writing {}", path.display()))?;
+    Ok(())
+}
+
+pub(crate) fn render(args: &RenderArgs) -> Result<()> {
+    let region_dir = dimension_dir(&args.world, &args.dimension).join("region");
+    let region_files: Vec<PathBuf> = list_region_files(&region_dir)?
+        .into_iter()
+        .filter(|path| {
+            region_coords(path).is_ok_and(|(reg_x, reg_z)| {
+                let rf = (reg_x * 512, reg_z * 512);
+                let rt = (reg_x * 512 + 511, reg_z * 512 + 511);
+                rect_intersects_bounds(rf, rt, args.from.as_ref(), args.to.as_ref())
+            })
+        })
+        .collect();
+    let palette = BlockPalette::load(args.palette.as_deref())?;
+
+    log::info!("Rendering {} region files...", region_files.len());
+    let chunks: Vec<RenderedChunk> = region_files
+        .par_iter()
+        .map(|path| {
+            render_region(path, &palette, args.from.as_ref(), args.to.as_ref()).unwrap_or_else(
+                |e| {
+                    log::debug!("error reading region {}: {e}", path.display());
+                    vec![]
+                },
+            )
+        })
+        .flatten()
+        .collect();
+    let Some((f, t)) = image_extent(&chunks, args.from.as_ref(), args.to.as_ref()) else {
+        bail!("there are no chunks to render");
+    };
+
+    let scale = args.scale;
+    let width = (t.0 - f.0 + 1) as u64 * scale as u64;
+    let height = (t.1 - f.1 + 1) as u64 * scale as u64;
+    if width * height > MAX_PIXELS {
+        bail!("a {width}x{height} image is too big, use --from/--to or a smaller --scale");
+    }
+    let mut image = RgbaImage::new(width as u32, height as u32);
+
+    let heat = if args.heatmap {
+        let times = read_inhabited_time(&region_files)?;
+        // a log scale, or a few old chunks would wash out everything else
+        let max = times.values().copied().max().unwrap_or(0) as f64;
+        Some((times, (max + 1.0).ln().max(1.0)))
+    } else {
+        None
+    };
+
+    for ((x, z), pixels) in chunks.iter() {
+        let overlay = heat.as_ref().and_then(|(times, max)| {
+            let time = *times.get(&(*x, *z))? as f64;
+            Some(heat_color((time + 1.0).ln() / max))
+        });
+        for (i, pixel) in pixels.iter().enumerate() {
+            let bx = x * 16 + (i % 16) as i32;
+            let bz = z * 16 + (i / 16) as i32;
+            if !(f.0..=t.0).contains(&bx) || !(f.1..=t.1).contains(&bz) {
+                continue;
+            }
+            let color = match overlay {
+                Some(heat) => blend(*pixel, heat, 0.5),
+                None => *pixel,
+            };
+            let px = (bx - f.0) as u32 * scale;
+            let pz = (bz - f.1) as u32 * scale;
+            for dz in 0..scale {
+                for dx in 0..scale {
+                    image.put_pixel(px + dx, pz + dz, image::Rgba(color));
+                }
+            }
+        }
+    }
+
+    image
+        .save(&args.output)
+        .with_context(|| format!("writing {}", args.output.display()))?;
+    log::info!(
+        "rendered {} chunks to {} ({width}x{height}), top left is block {} {}",
+        chunks.len(),
+        args.output.display(),
+        f.0,
+        f.1
+    );
+
+    if let Ok(unknown) = palette.unknown.lock() {
+        if !unknown.is_empty() {
+            log::warn!(
+                "{} blocks have no color in the palette: {}",
+                unknown.len(),
+                unknown.iter().cloned().collect::<Vec<_>>().join(", ")
+            );
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::snbt;
+    use serde_json::json;
+
+    #[test]
+    fn colors() {
+        assert_eq!(parse_color(&json!([1, 2, 3])), Some([1, 2, 3, 255]));
+        assert_eq!(parse_color(&json!([1, 2, 3, 4])), Some([1, 2, 3, 4]));
+        assert_eq!(parse_color(&json!("#0a0b0c")), Some([10, 11, 12, 255]));
+        assert_eq!(parse_color(&json!("#0a0b0c80")), Some([10, 11, 12, 128]));
+        for invalid in [
+            json!([1, 2]),
+            json!([256, 0, 0]),
+            json!([-1, 0, 0]),
+            json!("0a0b0c"),
+            json!("#0a0b0"),
+            json!("#zz0b0c"),
+            json!(7),
+        ] {
+            assert_eq!(parse_color(&invalid), None, "{invalid}");
+        }
+    }
+
+    fn block(text: &str) -> Block {
+        fastnbt::from_value(&snbt::parse(text).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn palette_fallbacks() {
+        let palette = BlockPalette {
+            colors: HashMap::from([
+                ("minecraft:oak_log|axis=y".to_string(), [1, 1, 1, 255]),
+                ("minecraft:oak_log".to_string(), [2, 2, 2, 255]),
+            ]),
+            unknown: Mutex::new(BTreeSet::new()),
+        };
+        let pick = |text| palette.pick(&block(text), None);
+        assert_eq!(
+            pick(r#"{Name:"minecraft:oak_log",Properties:{axis:"y"}}"#),
+            [1, 1, 1, 255]
+        );
+        assert_eq!(
+            pick(r#"{Name:"minecraft:oak_log",Properties:{axis:"x"}}"#),
+            [2, 2, 2, 255]
+        );
+        assert_eq!(pick(r#"{Name:"minecraft:oak_button"}"#), [0, 0, 0, 0]);
+        assert_eq!(pick(r#"{Name:"mod:mystery"}"#), UNKNOWN);
+        assert_eq!(
+            *palette.unknown.lock().unwrap(),
+            BTreeSet::from(["mod:mystery".to_string()])
+        );
+    }
+
+    #[test]
+    fn extent() {
+        let chunks = [((0, 0), [GRID; 256]), ((2, 1), [GRID; 256])];
+        assert_eq!(image_extent(&chunks, None, None), Some(((0, 0), (47, 31))));
+        // corners in any order
+        assert_eq!(
+            image_extent(&chunks, Some(&(40, 0, -10)), Some(&(5, 0, 20))),
+            Some(((5, 0), (40, 20)))
+        );
+        assert_eq!(
+            image_extent(&chunks, Some(&(-20, 0, 3)), None),
+            Some(((0, 3), (47, 31)))
+        );
+        assert_eq!(
+            image_extent(&chunks, None, Some(&(30, 0, 100))),
+            Some(((0, 0), (30, 31)))
+        );
+        assert_eq!(image_extent(&[], None, None), None);
+    }
+}
@@ -0,0 +1,251 @@
+use crate::backup::Backup;
+use crate::cli::RepairArgs;
+use crate::nbt::Compound;
+use crate::region::{
+    decompress, external_chunk_path, read_chunk_payload, read_header, region_coords, stored_coords,
+    world_region_files, write_region, ChunkLocation, StoredChunk, SECTOR_SIZE,
+};
+
+use anyhow::{anyhow, Result};
+use fastnbt::from_bytes;
+use std::collections::hash_map::Entry;
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, File};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+/// A chunk that can't be kept, and the bytes its slot pointed at
+struct Quarantined {
+    x: i32,
+    z: i32,
+    reason: String,
+    bytes: Vec<u8>,
+}
+
+/// Reads the bytes a slot points at, as far as the file goes
+fn slot_bytes(file: &mut File, location: &ChunkLocation) -> Result<Vec<u8>> {
+    let mut bytes = vec![];
+    file.seek(SeekFrom::Start(location.sector * SECTOR_SIZE))?;
+    file.take(location.sectors * SECTOR_SIZE)
+        .read_to_end(&mut bytes)?;
+    Ok(bytes)
+}
+
+/// Decodes a stored chunk, to make sure it's readable and to find out
+/// where it belongs
+fn decode(path: &Path, x: i32, z: i32, compression: u8, data: &[u8]) -> Result<Compound> {
+    let data = if compression >= 128 {
+        decompress(
+            compression - 128,
+            &fs::read(external_chunk_path(path, x, z))?,
+        )?
+    } else {
+        decompress(compression, data)?
+    };
+    Ok(from_bytes(&data)?)
+}
+
+/// Appends chunks to the quarantine file next to a region file: each one
+/// is a line of text describing it followed by the raw bytes of its slot.
+fn quarantine(path: &Path, chunks: &[Quarantined], backup: &Backup) -> Result<PathBuf> {
+    let quarantine = path.with_extension("mca.quarantine");
+    backup.save(&quarantine)?;
+    let mut file = File::options()
+        .create(true)
+        .append(true)
+        .open(&quarantine)?;
+    for chunk in chunks {
+        writeln!(
+            file,
+            "mcl quarantine: chunk {} {}, {} bytes, {}",
+            chunk.x,
+            chunk.z,
+            chunk.bytes.len(),
+            chunk.reason
+        )?;
+        file.write_all(&chunk.bytes)?;
+        writeln!(file)?;
+    }
+    backup.record(&quarantine, format!("quarantined {} chunks", chunks.len()))?;
+    Ok(quarantine)
+}
+
+/// Rebuilds a region file from the chunks that can still be decoded.
+/// Returns whether the file needed it.
+fn repair_file(path: &Path, dry_run: bool, backup: &Backup) -> Result<bool> {
+    let (reg_x, reg_z) = region_coords(path)?;
+    let len = fs::metadata(path)?.len();
+    if len == 0 {
+        return Ok(false);
+    }
+
+    let mut file = File::open(path)?;
+    let locations = match read_header(&mut file) {
+        Ok(locations) => locations,
+        Err(e) => {
+            println!("{}: can't repair: {e}", path.display());
+            return Ok(false);
+        }
+    };
+
+    let mut needs_repair = len % SECTOR_SIZE != 0;
+    let mut used_sectors = 2;
+    let mut owned_sectors: HashSet<u64> = HashSet::new();
+    // chunks that are in the right slot, and chunks that belong in another
+    // slot of this region
+    let mut in_place: Vec<StoredChunk> = vec![];
+    let mut misplaced: Vec<(StoredChunk, ChunkLocation)> = vec![];
+    let mut quarantined: Vec<Quarantined> = vec![];
+
+    for loc in locations.iter() {
+        let x = reg_x * 32 + loc.x as i32;
+        let z = reg_z * 32 + loc.z as i32;
+
+        let payload = if loc.sector < 2 {
+            Err(anyhow!("points into the header"))
+        } else if loc.sectors == 0 {
+            Err(anyhow!("has an offset but no sectors"))
+        } else {
+            read_chunk_payload(&mut file, loc)
+        };
+        let decoded = payload.and_then(|(compression, data)| {
+            let chunk = decode(path, x, z, compression, &data)?;
+            Ok((compression, data, chunk))
+        });
+        let (compression, data, chunk) = match decoded {
+            Ok(decoded) => decoded,
+            Err(e) => {
+                needs_repair = true;
+                let bytes = if loc.sector < 2 {
+                    vec![]
+                } else {
+                    slot_bytes(&mut file, loc)?
+                };
+                quarantined.push(Quarantined {
+                    x,
+                    z,
+                    reason: e.to_string(),
+                    bytes,
+                });
+                continue;
+            }
+        };
+        used_sectors += loc.sectors;
+        for sector in loc.sector..loc.sector + loc.sectors {
+            if !owned_sectors.insert(sector) {
+                needs_repair = true;
+            }
+        }
+
+        let (sx, sz) = stored_coords(&chunk).unwrap_or((x, z));
+        let stored = StoredChunk {
+            x: sx.rem_euclid(32) as usize,
+            z: sz.rem_euclid(32) as usize,
+            timestamp: loc.timestamp,
+            compression,
+            data,
+        };
+        if (sx, sz) == (x, z) {
+            in_place.push(stored);
+        } else if (sx >> 5, sz >> 5) == (reg_x, reg_z) {
+            needs_repair = true;
+            misplaced.push((stored, loc.clone()));
+        } else {
+            needs_repair = true;
+            quarantined.push(Quarantined {
+                x,
+                z,
+                reason: format!("belongs to chunk {sx} {sz}, in another region"),
+                bytes: slot_bytes(&mut file, loc)?,
+            });
+        }
+    }
+
+    // free space left by removed or moved chunks
+    if used_sectors * SECTOR_SIZE < len {
+        needs_repair = true;
+    }
+    if !needs_repair {
+        return Ok(false);
+    }
+
+    let mut slots: HashMap<(usize, usize), StoredChunk> = HashMap::new();
+    for chunk in in_place {
+        slots.insert((chunk.x, chunk.z), chunk);
+    }
+    for (chunk, loc) in misplaced {
+        let (x, z) = (reg_x * 32 + chunk.x as i32, reg_z * 32 + chunk.z as i32);
+        match slots.entry((chunk.x, chunk.z)) {
+            // quarantined like the others, as the slot it was found in
+            Entry::Occupied(_) => quarantined.push(Quarantined {
+                x: reg_x * 32 + loc.x as i32,
+                z: reg_z * 32 + loc.z as i32,
+                reason: format!("belongs to chunk {x} {z}, but that slot is taken"),
+                bytes: slot_bytes(&mut file, &loc)?,
+            }),
+            Entry::Vacant(slot) => {
+                println!("{}: moving chunk {x} {z} to its slot", path.display());
+                slot.insert(chunk);
+            }
+        }
+    }
+
+    for q in quarantined.iter() {
+        println!(
+            "{}: quarantining chunk {} {}: {}",
+            path.display(),
+            q.x,
+            q.z,
+            q.reason
+        );
+    }
+
+    let mut chunks: Vec<StoredChunk> = slots.into_values().collect();
+    chunks.sort_by_key(|c| (c.z, c.x));
+    if dry_run {
+        println!(
+            "{}: would be rebuilt with {} chunks",
+            path.display(),
+            chunks.len()
+        );
+        return Ok(true);
+    }
+
+    drop(file);
+    backup.save(path)?;
+    if !quarantined.is_empty() {
+        let q = quarantine(path, &quarantined, backup)?;
+        log::info!(
+            "quarantined {} chunks in {}",
+            quarantined.len(),
+            q.display()
+        );
+    }
+    let new_len = write_region(path, &chunks)?;
+    backup.record(
+        path,
+        format!(
+            "rebuilt with {} chunks, {} quarantined",
+            chunks.len(),
+            quarantined.len()
+        ),
+    )?;
+    println!(
+        "{}: rebuilt with {} chunks, {} bytes reclaimed",
+        path.display(),
+        chunks.len(),
+        len.saturating_sub(new_len)
+    );
+    Ok(true)
+}
+
+pub(crate) fn repair(args: &RepairArgs, backup: &Backup) -> Result<()> {
+    let mut repaired = 0;
+    for path in world_region_files(&args.world, args.dimension.as_ref())? {
+        if repair_file(&path, args.dry_run, backup)? {
+            repaired += 1;
+        }
+    }
+    log::info!("{repaired} region files needed repairs");
+    Ok(())
+}
@@ -0,0 +1,228 @@
+use anyhow::{bail, Context, Result};
+use fastanvil::Block;
+use regex::Regex;
+use serde::Deserialize;
+use std::collections::HashSet;
+use std::fs::File;
+use std::path::Path;
+
+/// How a block (or entity, or item) id is matched
+#[derive(Debug)]
+pub(crate) enum NameMatcher {
+    /// `minecraft:chest`
+    Exact(String),
+    /// `diamond`, a plain word matches every id containing it
+    Substring(String),
+    /// `*_log`
+    Glob(glob::Pattern),
+    /// `/.*_(log|wood)$/`
+    Regex(Regex),
+    /// `#minecraft:logs`, resolved from a tag file
+    Tag(HashSet<String>),
+}
+
+impl NameMatcher {
+    /// Parses a matcher for namespaced ids. Tags aren't handled here, since
+    /// they need a directory to be read from.
+    pub(crate) fn parse(pattern: &str) -> Result<NameMatcher> {
+        if pattern.contains(['*', '?']) && !pattern.starts_with('/') {
+            return Ok(NameMatcher::Glob(glob::Pattern::new(&namespaced(pattern))?));
+        }
+        if pattern.contains(':') && !pattern.starts_with('/') {
+            return Ok(NameMatcher::Exact(pattern.to_string()));
+        }
+        NameMatcher::parse_text(pattern)
+    }
+
+    /// Parses a matcher for free text, like custom names
+    pub(crate) fn parse_text(pattern: &str) -> Result<NameMatcher> {
+        if let Some(re) = pattern.strip_prefix('/').and_then(|p| p.strip_suffix('/')) {
+            return Ok(NameMatcher::Regex(Regex::new(re)?));
+        }
+        if pattern.contains(['*', '?']) {
+            return Ok(NameMatcher::Glob(glob::Pattern::new(pattern)?));
+        }
+        Ok(NameMatcher::Substring(pattern.to_string()))
+    }
+
+    pub(crate) fn matches(&self, name: &str) -> bool {
+        match self {
+            NameMatcher::Exact(id) => name == id,
+            NameMatcher::Substring(s) => name.contains(s.as_str()),
+            NameMatcher::Glob(pattern) => pattern.matches(name),
+            NameMatcher::Regex(re) => re.is_match(name),
+            NameMatcher::Tag(ids) => ids.contains(name),
+        }
+    }
+}
+
+fn namespaced(id: &str) -> String {
+    if id.contains(':') {
+        id.to_string()
+    } else {
+        format!("minecraft:{id}")
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum TagEntry {
+    Id(String),
+    Optional { id: String },
+}
+
+#[derive(Deserialize)]
+struct TagFile {
+    values: Vec<TagEntry>,
+}
+
+/// Resolves a block tag like `minecraft:logs` to the block ids it includes,
+/// following nested tags. `tags_dir` is the `data` directory of a datapack
+/// or of the extracted game jar.
+pub(crate) fn resolve_tag(tags_dir: &Path, tag: &str) -> Result<HashSet<String>> {
+    let mut ids = HashSet::new();
+    let mut seen = HashSet::new();
+    let mut pending = vec![namespaced(tag)];
+    while let Some(tag) = pending.pop() {
+        if !seen.insert(tag.clone()) {
+            continue;
+        }
+        let (namespace, path) = tag.split_once(':').context("parsing tag")?;
+        // the folder was renamed from `blocks` to `block` in 1.21
+        let file = ["block", "blocks"]
+            .iter()
+            .map(|folder| {
+                tags_dir
+                    .join(namespace)
+                    .join("tags")
+                    .join(folder)
+                    .join(format!("{path}.json"))
+            })
+            .find(|f| f.is_file())
+            .with_context(|| format!("tag #{tag} not found in `{}`", tags_dir.display()))?;
+        let tag_file: TagFile = serde_json::from_reader(File::open(&file)?)
+            .with_context(|| format!("Failed to parse `{}`", file.display()))?;
+        for entry in tag_file.values {
+            let (TagEntry::Id(id) | TagEntry::Optional { id }) = entry;
+            match id.strip_prefix('#') {
+                Some(nested) => pending.push(namespaced(nested)),
+                None => {
+                    ids.insert(namespaced(&id));
+                }
+            }
+        }
+    }
+    Ok(ids)
+}
+
+/// A block state selector, such as `minecraft:chest[type=left]`,
+/// `*_log[axis=y]`, `/.*_ore$/` or `#minecraft:logs`.
+#[derive(Debug)]
+pub(crate) struct BlockSelector {
+    name: NameMatcher,
+    properties: Vec<(String, String)>,
+}
+
+impl BlockSelector {
+    pub(crate) fn parse(selector: &str, tags_dir: Option<&Path>) -> Result<BlockSelector> {
+        let (name, properties) = split_properties(selector)?;
+
+        let name = match name.strip_prefix('#') {
+            Some(tag) => {
+                let tags_dir = tags_dir.context("block tags need a --tags directory")?;
+                NameMatcher::Tag(resolve_tag(tags_dir, tag)?)
+            }
+            None => NameMatcher::parse(name)?,
+        };
+
+        Ok(BlockSelector { name, properties })
+    }
+
+    pub(crate) fn matches(&self, block: &Block) -> bool {
+        self.matches_description(block.name(), block.encoded_description())
+    }
+
+    /// `encoded` is the block name followed by its sorted properties, as in
+    /// `minecraft:chest|facing=north,type=left,waterlogged=false`
+    fn matches_description(&self, name: &str, encoded: &str) -> bool {
+        if !self.name.matches(name) {
+            return false;
+        }
+        if self.properties.is_empty() {
+            return true;
+        }
+        let state: Vec<(&str, &str)> = encoded
+            .split_once('|')
+            .map(|(_, props)| props)
+            .unwrap_or_default()
+            .split(',')
+            .filter_map(|prop| prop.split_once('='))
+            .collect();
+        self.properties
+            .iter()
+            .all(|(k, v)| state.iter().any(|(sk, sv)| sk == k && sv == v))
+    }
+}
+
+/// Splits `name[prop=value,...]` into the name and the properties
+fn split_properties(selector: &str) -> Result<(&str, Vec<(String, String)>)> {
+    // a regex may contain brackets of its own
+    let name_end = if let Some(regex) = selector.strip_prefix('/') {
+        regex
+            .rfind('/')
+            .map(|i| i + 2)
+            .context("unterminated regex")?
+    } else {
+        selector.find('[').unwrap_or(selector.len())
+    };
+    let (name, rest) = selector.split_at(name_end);
+    if rest.is_empty() {
+        return Ok((name, vec![]));
+    }
+
+    let Some(props) = rest.strip_prefix('[').and_then(|r| r.strip_suffix(']')) else {
+        bail!("expected [property=value,...] after {name}");
+    };
+    let mut properties = vec![];
+    for prop in props.split(',').map(str::trim).filter(|p| !p.is_empty()) {
+        let (k, v) = prop
+            .split_once('=')
+            .with_context(|| format!("expected property=value, found {prop}"))?;
+        properties.push((k.trim().to_string(), v.trim().to_string()));
+    }
+    Ok((name, properties))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn matches(selector: &str, encoded: &str) -> bool {
+        let selector = BlockSelector::parse(selector, None).unwrap();
+        let name = encoded.split('|').next().unwrap();
+        selector.matches_description(name, encoded)
+    }
+
+    #[test]
+    fn selectors() {
+        let chest = "minecraft:chest|facing=north,type=left,waterlogged=false";
+        assert!(matches("minecraft:chest", chest));
+        assert!(matches("minecraft:chest[type=left]", chest));
+        assert!(!matches("minecraft:chest[type=right]", chest));
+        assert!(!matches("minecraft:trapped_chest", chest));
+        assert!(matches("chest", chest));
+
+        let log = "minecraft:oak_log|axis=y";
+        assert!(matches("*_log[axis=y]", log));
+        assert!(!matches("*_log[axis=x]", log));
+        assert!(matches("/^minecraft:(oak|birch)_log$/[axis=y]", log));
+        assert!(!matches("/birch/", log));
+
+        assert!(matches(
+            "redstone_wire[power=15]",
+            "minecraft:redstone_wire|power=15"
+        ));
+        assert!(BlockSelector::parse("chest[type]", None).is_err());
+        assert!(BlockSelector::parse("#minecraft:logs", None).is_err());
+    }
+}
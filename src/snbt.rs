@@ -0,0 +1,368 @@
+//! Stringified NBT, the text format Minecraft uses in commands, as in
+//! `{id:"minecraft:chest",Items:[{Slot:0b,id:"minecraft:stone",count:1}]}`.
+
+use crate::nbt::Compound;
+
+use anyhow::{bail, Result};
+use fastnbt::{ByteArray, IntArray, LongArray, Value};
+use std::fmt::Write;
+
+/// Characters allowed in unquoted keys and values
+pub(crate) fn is_unquoted(c: char) -> bool {
+    c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '.' | '+')
+}
+
+/// Reads SNBT values out of a string. Also used by the NBT path parser,
+/// which embeds compounds in paths.
+pub(crate) struct Reader<'a> {
+    text: &'a str,
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    pub(crate) fn new(text: &'a str) -> Self {
+        Reader { text, pos: 0 }
+    }
+
+    pub(crate) fn peek(&self) -> Option<char> {
+        self.text[self.pos..].chars().next()
+    }
+
+    fn next(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.pos += c.len_utf8();
+        Some(c)
+    }
+
+    pub(crate) fn skip_whitespace(&mut self) {
+        while self.peek().is_some_and(char::is_whitespace) {
+            self.next();
+        }
+    }
+
+    /// Consumes `c` if it's the next non-whitespace character
+    pub(crate) fn eat(&mut self, c: char) -> bool {
+        self.skip_whitespace();
+        if self.peek() == Some(c) {
+            self.next();
+            true
+        } else {
+            false
+        }
+    }
+
+    pub(crate) fn expect(&mut self, c: char) -> Result<()> {
+        if !self.eat(c) {
+            bail!("expected '{c}' at position {} of {}", self.pos, self.text);
+        }
+        Ok(())
+    }
+
+    pub(crate) fn is_done(&mut self) -> bool {
+        self.skip_whitespace();
+        self.pos == self.text.len()
+    }
+
+    pub(crate) fn unquoted(&mut self, allowed: impl Fn(char) -> bool) -> &'a str {
+        let start = self.pos;
+        while self.peek().is_some_and(&allowed) {
+            self.next();
+        }
+        &self.text[start..self.pos]
+    }
+
+    pub(crate) fn quoted(&mut self) -> Result<String> {
+        self.skip_whitespace();
+        let quote = match self.next() {
+            Some(q @ ('"' | '\'')) => q,
+            _ => bail!(
+                "expected a quoted string at position {} of {}",
+                self.pos,
+                self.text
+            ),
+        };
+        let mut s = String::new();
+        loop {
+            match self.next() {
+                None => bail!("unterminated string in {}", self.text),
+                Some('\\') => match self.next() {
+                    Some('n') => s.push('\n'),
+                    Some('r') => s.push('\r'),
+                    Some('t') => s.push('\t'),
+                    Some(c) => s.push(c),
+                    None => bail!("unterminated string in {}", self.text),
+                },
+                Some(c) if c == quote => return Ok(s),
+                Some(c) => s.push(c),
+            }
+        }
+    }
+
+    fn key(&mut self) -> Result<String> {
+        self.skip_whitespace();
+        if matches!(self.peek(), Some('"' | '\'')) {
+            return self.quoted();
+        }
+        let key = self.unquoted(is_unquoted);
+        if key.is_empty() {
+            bail!("expected a key at position {} of {}", self.pos, self.text);
+        }
+        Ok(key.to_string())
+    }
+
+    pub(crate) fn compound(&mut self) -> Result<Compound> {
+        self.expect('{')?;
+        let mut compound = Compound::new();
+        if self.eat('}') {
+            return Ok(compound);
+        }
+        loop {
+            let key = self.key()?;
+            self.expect(':')?;
+            let value = self.value()?;
+            compound.insert(key, value);
+            if !self.eat(',') {
+                self.expect('}')?;
+                return Ok(compound);
+            }
+        }
+    }
+
+    pub(crate) fn value(&mut self) -> Result<Value> {
+        self.skip_whitespace();
+        match self.peek() {
+            Some('{') => Ok(Value::Compound(self.compound()?)),
+            Some('[') => self.list(),
+            Some('"' | '\'') => Ok(Value::String(self.quoted()?)),
+            _ => {
+                let token = self.unquoted(is_unquoted);
+                if token.is_empty() {
+                    bail!("expected a value at position {} of {}", self.pos, self.text);
+                }
+                Ok(scalar(token))
+            }
+        }
+    }
+
+    fn list(&mut self) -> Result<Value> {
+        self.expect('[')?;
+
+        // typed arrays look like [B;1b,2b], [I;1,2] and [L;1L,2L]
+        let start = self.pos;
+        self.skip_whitespace();
+        if let Some(kind @ ('B' | 'I' | 'L')) = self.peek() {
+            self.next();
+            if self.eat(';') {
+                return self.array(kind);
+            }
+        }
+        self.pos = start;
+
+        let mut items = vec![];
+        if self.eat(']') {
+            return Ok(Value::List(items));
+        }
+        loop {
+            items.push(self.value()?);
+            if !self.eat(',') {
+                self.expect(']')?;
+                return Ok(Value::List(items));
+            }
+        }
+    }
+
+    fn array(&mut self, kind: char) -> Result<Value> {
+        let mut numbers: Vec<i64> = vec![];
+        if !self.eat(']') {
+            loop {
+                self.skip_whitespace();
+                let token = self.unquoted(is_unquoted);
+                let n = match scalar(token) {
+                    Value::Byte(n) => n as i64,
+                    Value::Short(n) => n as i64,
+                    Value::Int(n) => n as i64,
+                    Value::Long(n) => n,
+                    _ => bail!(
+                        "expected a number in array, found '{token}' in {}",
+                        self.text
+                    ),
+                };
+                numbers.push(n);
+                if !self.eat(',') {
+                    self.expect(']')?;
+                    break;
+                }
+            }
+        }
+        Ok(match kind {
+            'B' => Value::ByteArray(ByteArray::new(numbers.iter().map(|&n| n as i8).collect())),
+            'I' => Value::IntArray(IntArray::new(numbers.iter().map(|&n| n as i32).collect())),
+            _ => Value::LongArray(LongArray::new(numbers)),
+        })
+    }
+}
+
+/// Interprets an unquoted token: numbers with an optional type suffix,
+/// `true` and `false` as bytes, and anything else as a string.
+fn scalar(token: &str) -> Value {
+    match token {
+        "true" => return Value::Byte(1),
+        "false" => return Value::Byte(0),
+        _ => {}
+    }
+    let (number, suffix) = match token.char_indices().last() {
+        Some((i, c)) if c.is_ascii_alphabetic() => (&token[..i], Some(c.to_ascii_lowercase())),
+        _ => (token, None),
+    };
+    let parsed = match suffix {
+        Some('b') => number.parse().ok().map(Value::Byte),
+        Some('s') => number.parse().ok().map(Value::Short),
+        Some('l') => number.parse().ok().map(Value::Long),
+        Some('f') => number.parse().ok().map(Value::Float),
+        Some('d') => number.parse().ok().map(Value::Double),
+        None => number.parse().ok().map(Value::Int).or_else(|| {
+            number
+                .parse()
+                .ok()
+                .filter(|_| number.contains(['.', 'e', 'E']))
+                .map(Value::Double)
+        }),
+        _ => None,
+    };
+    parsed.unwrap_or_else(|| Value::String(token.to_string()))
+}
+
+/// Parses a complete SNBT value
+pub(crate) fn parse(text: &str) -> Result<Value> {
+    let mut reader = Reader::new(text);
+    let value = reader.value()?;
+    if !reader.is_done() {
+        bail!("unexpected text after value in {text}");
+    }
+    Ok(value)
+}
+
+fn write_string(out: &mut String, s: &str) {
+    out.push('"');
+    for c in s.chars() {
+        // records are one per line, so line breaks can't go out raw
+        match c {
+            '"' | '\\' => {
+                out.push('\\');
+                out.push(c);
+            }
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
+
+fn write_array<T: std::fmt::Display>(out: &mut String, kind: char, suffix: &str, values: &[T]) {
+    let _ = write!(out, "[{kind};");
+    for (i, n) in values.iter().enumerate() {
+        if i > 0 {
+            out.push(',');
+        }
+        let _ = write!(out, "{n}{suffix}");
+    }
+    out.push(']');
+}
+
+fn write_compound(out: &mut String, c: &Compound) {
+    // sorted, so the same compound always prints the same way
+    let mut keys: Vec<&String> = c.keys().collect();
+    keys.sort();
+    out.push('{');
+    for (i, key) in keys.into_iter().enumerate() {
+        if i > 0 {
+            out.push(',');
+        }
+        if !key.is_empty() && key.chars().all(is_unquoted) {
+            out.push_str(key);
+        } else {
+            write_string(out, key);
+        }
+        out.push(':');
+        write_value(out, &c[key]);
+    }
+    out.push('}');
+}
+
+fn write_value(out: &mut String, value: &Value) {
+    let _ = match value {
+        Value::Byte(n) => write!(out, "{n}b"),
+        Value::Short(n) => write!(out, "{n}s"),
+        Value::Int(n) => write!(out, "{n}"),
+        Value::Long(n) => write!(out, "{n}L"),
+        Value::Float(n) => write!(out, "{n}f"),
+        Value::Double(n) => write!(out, "{n}d"),
+        Value::String(s) => {
+            write_string(out, s);
+            Ok(())
+        }
+        Value::ByteArray(a) => {
+            write_array(out, 'B', "b", a);
+            Ok(())
+        }
+        Value::IntArray(a) => {
+            write_array(out, 'I', "", a);
+            Ok(())
+        }
+        Value::LongArray(a) => {
+            write_array(out, 'L', "L", a);
+            Ok(())
+        }
+        Value::List(items) => {
+            out.push('[');
+            for (i, item) in items.iter().enumerate() {
+                if i > 0 {
+                    out.push(',');
+                }
+                write_value(out, item);
+            }
+            out.push(']');
+            Ok(())
+        }
+        Value::Compound(c) => {
+            write_compound(out, c);
+            Ok(())
+        }
+    };
+}
+
+/// Formats a value as SNBT, keeping every tag type so it can be parsed back
+pub(crate) fn to_snbt(value: &Value) -> String {
+    let mut out = String::new();
+    write_value(&mut out, value);
+    out
+}
+
+pub(crate) fn compound_to_snbt(c: &Compound) -> String {
+    let mut out = String::new();
+    write_compound(&mut out, c);
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let text = r#"{Items:[{Slot:0b,count:3,id:"minecraft:stone"}],Pos:[1.5d,64d,-2.25d],"a key":"say \"hi\"",heights:[L;1L,-2L],light:[B;0b,15b],seed:-4L,small:2s,speed:0.5f,tags:[]}"#;
+        let value = parse(text).unwrap();
+        assert_eq!(to_snbt(&value), text);
+
+        let Value::Compound(c) = parse("{a:1,b:2.5,c:true,d:[I; 1, 2],e:stone}").unwrap() else {
+            panic!("expected a compound");
+        };
+        assert_eq!(c["a"], Value::Int(1));
+        assert_eq!(c["b"], Value::Double(2.5));
+        assert_eq!(c["c"], Value::Byte(1));
+        assert_eq!(c["d"], Value::IntArray(IntArray::new(vec![1, 2])));
+        assert_eq!(c["e"], Value::String("stone".to_string()));
+    }
+}